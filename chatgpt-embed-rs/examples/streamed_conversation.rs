@@ -22,7 +22,7 @@ async fn main() -> Result<()> {
     // Iterating over a stream and collecting the results into a vector
     let mut output: Vec<ResponseChunk> = Vec::new();
     while let Some(chunk) = stream.next().await {
-        match chunk {
+        match chunk? {
             ResponseChunk::Content {
                 delta,
                 response_index,
@@ -52,10 +52,10 @@ async fn main() -> Result<()> {
     another_stream
         .for_each(|each| async move {
             match each {
-                ResponseChunk::Content {
+                Ok(ResponseChunk::Content {
                     delta,
                     response_index: _,
-                } => {
+                }) => {
                     // Printing part of response without the newline
                     print!("{delta}");
                     // Manually flushing the standard output, as `print` macro does not do that
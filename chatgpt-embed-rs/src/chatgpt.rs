@@ -13,6 +13,8 @@ pub mod err;
 #[cfg(feature = "functions")]
 /// Contains API for function calling
 pub mod functions;
+/// Retry and backoff policy applied to API requests
+pub mod retry;
 /// The prelude module. Import everything from it to get the necessary elements from this library
 pub mod prelude;
 /// Types returned from the API and sent to it
@@ -114,8 +116,8 @@ pub mod test {
         let response = client
             .send_message_streaming("Could you give me names of three popular Rust web frameworks?")
             .await?;
-        let collected = response.collect::<Vec<ResponseChunk>>().await;
-        assert_eq!(collected.last().unwrap().to_owned(), ResponseChunk::Done);
+        let collected = response.collect::<Vec<crate::Result<ResponseChunk>>>().await;
+        assert_eq!(collected.last().unwrap().as_ref().unwrap(), &ResponseChunk::Done);
         Ok(())
     }
 
@@ -129,8 +131,8 @@ pub mod test {
         let streamed = conv
             .send_message_streaming("Now could you do the same but for Kotlin?")
             .await?;
-        let collected = streamed.collect::<Vec<ResponseChunk>>().await;
-        assert_eq!(collected.last().unwrap().to_owned(), ResponseChunk::Done);
+        let collected = streamed.collect::<Vec<crate::Result<ResponseChunk>>>().await;
+        assert_eq!(collected.last().unwrap().as_ref().unwrap(), &ResponseChunk::Done);
         Ok(())
     }
 
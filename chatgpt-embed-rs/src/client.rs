@@ -6,8 +6,9 @@ use reqwest::{self, Proxy};
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
-#[cfg(feature = "streams")]
 use reqwest::Response;
+use serde::Serialize;
+use url::Url;
 #[cfg(feature = "streams")]
 use {
     crate::types::InboundChunkPayload, crate::types::InboundResponseChunk,
@@ -16,6 +17,7 @@ use {
 
 use crate::config::ModelConfiguration;
 use crate::converse::Conversation;
+use crate::retry::{is_retryable_error, is_retryable_status};
 use crate::types::*;
 
 #[cfg(feature = "functions")]
@@ -139,36 +141,42 @@ impl ChatGPT {
         Conversation::new(self.clone(), direction_message.into())
     }
 
+    /// Posts a JSON body to the API, retrying rate-limited, timed out and server-failed
+    /// requests according to the configured [`crate::retry::RetryPolicy`].
+    ///
+    /// Once retries are exhausted, the last response is returned as is.
+    async fn post<T: Serialize + ?Sized>(&self, url: &Url, body: &T) -> crate::Result<Response> {
+        let policy = &self.config.retry_policy;
+        let mut attempt = 1;
+        loop {
+            let result = self.client.post(url.clone()).json(body).send().await;
+            let retry_delay = match &result {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    policy.delay(attempt, Some(response.headers()))
+                }
+                Err(error) if is_retryable_error(error) => policy.delay(attempt, None),
+                _ => return Ok(result?),
+            };
+            if attempt >= policy.max_attempts {
+                return Ok(result?);
+            }
+            tokio::time::sleep(retry_delay).await;
+            attempt += 1;
+        }
+    }
+
     /// Gets embeddings for a specific text input.
     pub async fn get_embeddings(
         &self,
-        text: &String
+        text: &str
     ) -> crate::Result<EmbeddingCompletionResponse> {
         let response = self
-            .client
-            .post(self.config.embed_api_url.clone())
-            .json(&EmbeddingRequest {
-                input: text.as_str(),
+            .post(&self.config.embed_api_url, &EmbeddingRequest {
+                input: text,
                 model: self.config.embed_engine.as_ref(),
             })
-            .send()
             .await?;
-            
-        //let headers = response
-        //    .headers();
-        
-        //for h in headers.iter() {
-        //    println!("header: {} = {}", h.0.to_string(), h.1.to_str().unwrap().to_string());
-        //}
-        //println!("header: {} = {}",
-        //    "x-ratelimit-remaining-requests",
-        //    headers.get("x-ratelimit-remaining-requests").unwrap().to_str().unwrap().to_string()
-        //);
-        //println!("header: {} = {}",
-        //    "x-ratelimit-remaining-tokens",
-        //    headers.get("x-ratelimit-remaining-tokens").unwrap().to_str().unwrap().to_string()
-        //);
-                    
+
         let json_response = 
             response.json()
                     .await?;
@@ -178,12 +186,59 @@ impl ChatGPT {
                 message: error.message,
                 error_type: error.error_type,
             }),
-            ServerResponse::Completion(_completion) => todo!("Todo"),
+            ServerResponse::Completion(_) => Err(crate::err::Error::ParsingError(
+                "Expected an embedding response".to_string(),
+            )),
             ServerResponse::EmbeddingCompletion(completion) => Ok(completion),
         } 
         
     }
 
+    /// Gets embeddings for many text inputs, packing them into as few requests as the
+    /// `embed_batch_size` and `embed_batch_tokens` configuration allows.
+    ///
+    /// The returned embeddings are in the same order as `inputs`.
+    pub async fn get_embeddings_batch(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(inputs.len());
+        for batch in self.config.embedding_batches(inputs) {
+            let batch_inputs = &inputs[batch];
+            let response: ServerResponse = self
+                .post(&self.config.embed_api_url, &BatchEmbeddingRequest {
+                    input: batch_inputs,
+                    model: self.config.embed_engine.as_ref(),
+                })
+                .await?
+                .json()
+                .await?;
+
+            let completion = match response {
+                ServerResponse::Error { error } => {
+                    return Err(crate::err::Error::BackendError {
+                        message: error.message,
+                        error_type: error.error_type,
+                    })
+                }
+                ServerResponse::Completion(_) => {
+                    return Err(crate::err::Error::ParsingError(
+                        "Expected an embedding response".to_string(),
+                    ))
+                }
+                ServerResponse::EmbeddingCompletion(completion) => completion,
+            };
+
+            let batch_embeddings = completion.into_ordered_embeddings();
+            if batch_embeddings.len() != batch_inputs.len() {
+                return Err(crate::err::Error::ParsingError(format!(
+                    "Expected {} embeddings but received {}",
+                    batch_inputs.len(),
+                    batch_embeddings.len()
+                )));
+            }
+            embeddings.extend(batch_embeddings);
+        }
+        Ok(embeddings)
+    }
+
     /// Explicitly sends whole message history to the API.
     ///
     /// In most cases, if you would like to store message history, you should be looking at the [`Conversation`] struct, and
@@ -193,9 +248,7 @@ impl ChatGPT {
         history: &Vec<ChatMessage>,
     ) -> crate::Result<CompletionResponse> {
         let response = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 messages: history,
                 stream: false,
@@ -208,16 +261,9 @@ impl ChatGPT {
                 #[cfg(feature = "functions")]
                 functions: &Vec::new(),
             })
-            .send()
             .await?;
 
-        //let headers = response
-        //    .headers();
-        
-        //for h in headers.iter() {
-            //println!("header: {} = {}", h.0.to_string(), h.1.to_str().unwrap().to_string());
-        //}
-                    
+
         let json_response = 
             response.json()
                     .await?;
@@ -228,12 +274,14 @@ impl ChatGPT {
                 error_type: error.error_type,
             }),
             ServerResponse::Completion(completion) => Ok(completion),
-            ServerResponse::EmbeddingCompletion(_) => todo!("None"),
+            ServerResponse::EmbeddingCompletion(_) => Err(crate::err::Error::ParsingError(
+                "Expected a completion response".to_string(),
+            )),
         }
     }
 
     /// Explicitly sends whole message history to the API and returns the response as stream. **Stream will be empty** if
-    /// any errors are returned from the server.
+    /// any errors are returned from the server, and yields an error if the stream breaks off or cannot be parsed.
     ///
     /// In most cases, if you would like to store message history, you should be looking at the [`Conversation`] struct, and
     /// [`Self::new_conversation()`] and [`Self::new_conversation_directed()`]
@@ -243,11 +291,9 @@ impl ChatGPT {
     pub async fn send_history_streaming(
         &self,
         history: &Vec<ChatMessage>,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+    ) -> crate::Result<impl Stream<Item = crate::Result<ResponseChunk>>> {
         let response = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 stream: true,
                 messages: history,
@@ -260,7 +306,6 @@ impl ChatGPT {
                 #[cfg(feature = "functions")]
                 functions: &Vec::new(),
             })
-            .send()
             .await?;
 
         Self::process_streaming_response(response)
@@ -272,9 +317,7 @@ impl ChatGPT {
         message: S,
     ) -> crate::Result<CompletionResponse> {
         let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 messages: &vec![ChatMessage {
                     role: Role::User,
@@ -292,7 +335,6 @@ impl ChatGPT {
                 #[cfg(feature = "functions")]
                 functions: &Vec::new(),
             })
-            .send()
             .await?
             .json()
             .await?;
@@ -302,23 +344,23 @@ impl ChatGPT {
                 error_type: error.error_type,
             }),
             ServerResponse::Completion(completion) => Ok(completion),
-            ServerResponse::EmbeddingCompletion(_) => todo!("None"),
+            ServerResponse::EmbeddingCompletion(_) => Err(crate::err::Error::ParsingError(
+                "Expected a completion response".to_string(),
+            )),
         }
     }
 
     /// Sends a single message to the API, and returns the response as stream, without preserving message history. **Stream will be empty** if
-    /// any errors are returned from the server.
+    /// any errors are returned from the server, and yields an error if the stream breaks off or cannot be parsed.
     ///
     /// Requires the `streams` crate feature
     #[cfg(feature = "streams")]
     pub async fn send_message_streaming<S: Into<String>>(
         &self,
         message: S,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+    ) -> crate::Result<impl Stream<Item = crate::Result<ResponseChunk>>> {
         let response = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 messages: &vec![ChatMessage {
                     role: Role::User,
@@ -336,7 +378,6 @@ impl ChatGPT {
                 #[cfg(feature = "functions")]
                 functions: &Vec::new(),
             })
-            .send()
             .await?;
 
         Self::process_streaming_response(response)
@@ -345,7 +386,7 @@ impl ChatGPT {
     #[cfg(feature = "streams")]
     fn process_streaming_response(
         response: Response,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+    ) -> crate::Result<impl Stream<Item = crate::Result<ResponseChunk>>> {
         use eventsource_stream::Eventsource;
         use futures_util::StreamExt;
 
@@ -355,14 +396,24 @@ impl ChatGPT {
             .map(|response| {
                 let response_stream = response.bytes_stream().eventsource();
                 response_stream.map(move |part| {
-                    let chunk = &part.expect("Stream closed abruptly!").data;
-                    if chunk == "[DONE]" {
-                        return ResponseChunk::Done;
+                    let part = part.map_err(|e| {
+                        crate::err::Error::ParsingError(format!("Stream closed abruptly: {e}"))
+                    })?;
+                    if part.data == "[DONE]" {
+                        return Ok(ResponseChunk::Done);
                     }
-                    let data: InboundResponseChunk = serde_json::from_str(chunk)
-                        .expect("Invalid inbound streaming response payload!");
-                    let choice = data.choices[0].to_owned();
-                    match choice.delta {
+                    let data: InboundResponseChunk =
+                        serde_json::from_str(&part.data).map_err(|e| {
+                            crate::err::Error::ParsingError(format!(
+                                "Invalid inbound streaming response payload: {e}"
+                            ))
+                        })?;
+                    let choice = data.choices.into_iter().next().ok_or_else(|| {
+                        crate::err::Error::ParsingError(
+                            "Inbound streaming response has no choices".to_string(),
+                        )
+                    })?;
+                    Ok(match choice.delta {
                         InboundChunkPayload::AnnounceRoles { role } => {
                             ResponseChunk::BeginResponse {
                                 role,
@@ -376,7 +427,7 @@ impl ChatGPT {
                         InboundChunkPayload::Close {} => ResponseChunk::CloseResponse {
                             response_index: choice.index,
                         },
-                    }
+                    })
                 })
             })
             .map_err(crate::err::Error::from)
@@ -414,9 +465,7 @@ impl ChatGPT {
         baked_functions: Vec<serde_json::Value>,
     ) -> crate::Result<CompletionResponse> {
         let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 messages: &vec![ChatMessage {
                     role: Role::User,
@@ -434,7 +483,6 @@ impl ChatGPT {
                 #[cfg(feature = "functions")]
                 functions: &baked_functions,
             })
-            .send()
             .await?
             .json()
             .await?;
@@ -445,6 +493,9 @@ impl ChatGPT {
                 error_type: error.error_type,
             }),
             ServerResponse::Completion(completion) => Ok(completion),
+            ServerResponse::EmbeddingCompletion(_) => Err(crate::err::Error::ParsingError(
+                "Expected a completion response".to_string(),
+            )),
         }
     }
 
@@ -456,9 +507,7 @@ impl ChatGPT {
         functions: &Vec<serde_json::Value>,
     ) -> crate::Result<CompletionResponse> {
         let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
+            .post(&self.config.api_url, &CompletionRequest {
                 model: self.config.engine.as_ref(),
                 messages: history,
                 stream: false,
@@ -470,7 +519,6 @@ impl ChatGPT {
                 max_tokens: self.config.max_tokens,
                 functions,
             })
-            .send()
             .await?
             .json()
             .await?;
@@ -480,6 +528,9 @@ impl ChatGPT {
                 error_type: error.error_type,
             }),
             ServerResponse::Completion(completion) => Ok(completion),
+            ServerResponse::EmbeddingCompletion(_) => Err(crate::err::Error::ParsingError(
+                "Expected a completion response".to_string(),
+            )),
         }
     }
 }
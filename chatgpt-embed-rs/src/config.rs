@@ -1,8 +1,10 @@
 use std::{fmt::Display, str::FromStr};
+use std::ops::Range;
 use std::time::Duration;
 
 #[cfg(feature = "functions")]
 use crate::functions::FunctionValidationStrategy;
+use crate::retry::RetryPolicy;
 use derive_builder::Builder;
 use serde::Serialize;
 
@@ -32,6 +34,12 @@ pub struct ModelConfiguration {
     pub embed_api_url: url::Url,
     /// Timeout for the http requests sent to avoid potentially permanently hanging requests.
     pub timeout: Duration,
+    /// The maximum amount of inputs sent in a single embeddings request
+    pub embed_batch_size: usize,
+    /// The approximate maximum amount of tokens sent in a single embeddings request
+    pub embed_batch_tokens: usize,
+    /// How requests failing with rate limits, timeouts or server errors are retried
+    pub retry_policy: RetryPolicy,
     /// Strategy for function validation strategy. Whenever ChatGPT fails to call a function correctly, this strategy is applied.
     #[cfg(feature = "functions")]
     pub function_validation: FunctionValidationStrategy,
@@ -51,12 +59,45 @@ impl Default for ModelConfiguration {
             api_url: url::Url::from_str("https://api.openai.com/v1/chat/completions").unwrap(),
             embed_api_url: url::Url::from_str("https://api.openai.com/v1/embeddings").unwrap(),
             timeout: Duration::from_secs(10),
+            embed_batch_size: 128,
+            embed_batch_tokens: 100_000,
+            retry_policy: RetryPolicy::default(),
             #[cfg(feature = "functions")]
             function_validation: FunctionValidationStrategy::default(),
         }
     }
 }
 
+impl ModelConfiguration {
+    /// Splits embedding inputs into consecutive batches respecting `embed_batch_size` and `embed_batch_tokens`.
+    ///
+    /// Tokens are estimated at four bytes each. An input exceeding the token budget on its own is sent alone.
+    pub fn embedding_batches(&self, inputs: &[String]) -> Vec<Range<usize>> {
+        let max_size = self.embed_batch_size.max(1);
+        let mut batches = Vec::new();
+        let mut start = 0;
+        let mut tokens = 0;
+        for (i, input) in inputs.iter().enumerate() {
+            let input_tokens = estimate_tokens(input);
+            if i > start && (i - start == max_size || tokens + input_tokens > self.embed_batch_tokens) {
+                batches.push(start..i);
+                start = i;
+                tokens = 0;
+            }
+            tokens += input_tokens;
+        }
+        if start < inputs.len() {
+            batches.push(start..inputs.len());
+        }
+        batches
+    }
+}
+
+/// Roughly estimates the amount of tokens in a text, at four bytes per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
 /// The engine version for ChatGPT
 #[derive(Serialize, Debug, Default, Copy, Clone, PartialEq, PartialOrd)]
 #[allow(non_camel_case_types)]
@@ -103,3 +144,23 @@ impl AsRef<str> for ChatGPTEngine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_batches() {
+        let config = ModelConfiguration {
+            embed_batch_size: 2,
+            embed_batch_tokens: 3,
+            ..Default::default()
+        };
+        let inputs: Vec<String> = ["abcd", "abcd", "abcd", "abcdefghijklmnop", "abcd"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(vec![0..2, 2..3, 3..4, 4..5], config.embedding_batches(&inputs));
+        assert!(config.embedding_batches(&[]).is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 pub use crate::client::ChatGPT;
 pub use crate::config::{ChatGPTEngine, ModelConfiguration, ModelConfigurationBuilder};
 pub use crate::converse::Conversation;
+pub use crate::retry::RetryPolicy;
 #[cfg(feature = "functions")]
 pub use crate::functions::{gpt_function, FunctionValidationStrategy};
 #[cfg(feature = "streams")]
@@ -0,0 +1,196 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+/// Controls how requests that failed with a retryable error are repeated
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct RetryPolicy {
+    /// The maximum amount of attempts, including the first one. `1` disables retrying
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound for the exponential backoff delay
+    pub max_backoff: Duration,
+    /// Factor the backoff delay is multiplied by after every attempt
+    pub multiplier: f32,
+    /// Fraction of each backoff delay that is randomized, between `0.0` and `1.0`
+    pub jitter: f32,
+    /// Whether to wait for the delay announced in `Retry-After` and `x-ratelimit-reset-*` headers
+    pub respect_rate_limit_headers: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.25,
+            respect_rate_limit_headers: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Exponential backoff delay before retry number `attempt` (starting at 1), without jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(32) as i32;
+        let factor = (self.multiplier.max(1.0) as f64).powi(exponent);
+        let nanos = (self.initial_backoff.as_nanos() as f64 * factor).round();
+        Duration::from_nanos(nanos.min(self.max_backoff.as_nanos() as f64) as u64)
+    }
+
+    /// Delay before retry number `attempt`, with jitter applied and server-announced delays honored
+    pub fn delay(&self, attempt: u32, headers: Option<&HeaderMap>) -> Duration {
+        let backoff = self.backoff(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let jittered = backoff.mul_f32(1.0 - jitter + jitter * random_unit());
+
+        let announced = headers
+            .filter(|_| self.respect_rate_limit_headers)
+            .and_then(rate_limit_delay);
+        match announced {
+            Some(announced) => announced.max(jittered),
+            None => jittered,
+        }
+    }
+}
+
+/// Returns `true` for HTTP statuses worth retrying: rate limiting, request timeouts and server errors
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// Returns `true` for transport errors worth retrying, such as timeouts and refused connections
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error.status().is_some_and(is_retryable_status)
+}
+
+/// Reads the delay the server asked for from the `Retry-After`, `retry-after-ms`
+/// and exhausted `x-ratelimit-reset-*` headers, picking the longest one
+pub fn rate_limit_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let mut delays: Vec<Duration> = Vec::new();
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        delays.push(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(secs) = header(RETRY_AFTER.as_str()).and_then(|v| v.trim().parse::<f64>().ok()) {
+        delays.push(Duration::from_secs_f64(secs.max(0.0)));
+    }
+    for kind in ["requests", "tokens"] {
+        let remaining = header(&format!("x-ratelimit-remaining-{kind}"));
+        if remaining.map(str::trim) == Some("0") {
+            if let Some(reset) = header(&format!("x-ratelimit-reset-{kind}")).and_then(parse_reset_duration) {
+                delays.push(reset);
+            }
+        }
+    }
+    delays.into_iter().max()
+}
+
+/// Parses durations in the format used by the `x-ratelimit-reset-*` headers, e.g. `1s`, `6m0s` or `20ms`
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0f64;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "h" => number * 3600.0,
+            "m" => number * 60.0,
+            "s" => number,
+            "ms" => number / 1000.0,
+            _ => return None,
+        };
+        total += seconds;
+        rest = &rest[unit_len..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+/// Returns a pseudo-random number in `0.0..1.0`, good enough for spreading retries
+fn random_unit() -> f32 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    (hasher.finish() >> 40) as f32 / (1u64 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..Default::default()
+        };
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(350), policy.backoff(3));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_parse_reset_duration() {
+        assert_eq!(Some(Duration::from_secs(360)), parse_reset_duration("6m0s"));
+        assert_eq!(Some(Duration::from_millis(20)), parse_reset_duration("20ms"));
+        assert_eq!(Some(Duration::from_millis(1500)), parse_reset_duration("1.5s"));
+        assert_eq!(None, parse_reset_duration("soon"));
+    }
+
+    #[test]
+    fn test_rate_limit_delay() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        headers.insert("x-ratelimit-remaining-tokens", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("7s"));
+        headers.insert("x-ratelimit-remaining-requests", HeaderValue::from_static("10"));
+        headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("1m"));
+        assert_eq!(Some(Duration::from_secs(7)), rate_limit_delay(&headers));
+
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(Duration::from_secs(7), policy.delay(1, Some(&headers)));
+        assert_eq!(Duration::from_millis(500), policy.delay(1, None));
+    }
+}
@@ -84,6 +84,16 @@ pub struct EmbeddingRequest<'a> {
     pub model: &'a str,
 }
 
+/// A request struct sent to the API to request embeddings for several inputs at once
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchEmbeddingRequest<'a> {
+    /// Specifies the inputs of an embedding request.
+    pub input: &'a [String],
+
+    /// Selects an embedding engine.
+    pub model: &'a str,
+}
+
 /// A request struct sent to the API to request a message completion
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct CompletionRequest<'a> {
@@ -198,6 +208,15 @@ impl EmbeddingCompletionResponse {
         // Unwrap is safe here, as we know that at least one message choice is provided
         &self.data_choices.first().unwrap().embedding_data
     }
+
+    /// Consumes the response, returning every embedding ordered by its input index
+    pub fn into_ordered_embeddings(mut self) -> Vec<Vec<f32>> {
+        self.data_choices.sort_by_key(|data| data.index);
+        self.data_choices
+            .into_iter()
+            .map(|data| data.embedding_data)
+            .collect()
+    }
 }
 
 /// A message completion choice struct
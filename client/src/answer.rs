@@ -0,0 +1,62 @@
+use crate::embedder::Embedder;
+use crate::error::Result;
+use crate::prompt::{build_prompt, format_answer, Prompt, PromptConfig};
+use crate::retrieval::{retrieve, RetrievalConfig};
+use crate::store::{SearchFilter, VectorStore};
+use crate::tokenizer::Tokenizer;
+
+/// An answer from the chat model and the prompt it was given.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub prompt: Prompt,
+    pub text: String,
+}
+
+impl Answer {
+    /// Formats the answer followed by the sources it cites.
+    pub fn with_sources(&self) -> String {
+        format_answer(&self.prompt, &self.text)
+    }
+}
+
+/// Retrieves the `config.top_k` chunks selected by `filter` that best match `query` and builds
+/// the prompt answering it from them.
+#[allow(clippy::too_many_arguments)]
+pub async fn prepare_prompt(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    agent_prompt: &str,
+    query: &str,
+    config: &PromptConfig,
+    retrieval: &RetrievalConfig,
+    tokenizer: Option<&Tokenizer>,
+    filter: &SearchFilter,
+) -> Result<Prompt> {
+    let chunks = retrieve(store, embedder, query, config.top_k, filter, retrieval).await?;
+    Ok(build_prompt(agent_prompt, query, &chunks, config, tokenizer))
+}
+
+/// Sends a prompt to the chat model.
+pub async fn answer_prompt(embedder: &Embedder, prompt: Prompt) -> Result<Answer> {
+    let response = embedder.client().send_history(&prompt.messages).await?;
+    Ok(Answer {
+        text: response.message().content.clone(),
+        prompt,
+    })
+}
+
+/// Answers `query` from the indexed chunks selected by `filter`.
+#[allow(clippy::too_many_arguments)]
+pub async fn ask(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    agent_prompt: &str,
+    query: &str,
+    config: &PromptConfig,
+    retrieval: &RetrievalConfig,
+    tokenizer: Option<&Tokenizer>,
+    filter: &SearchFilter,
+) -> Result<Answer> {
+    let prompt = prepare_prompt(store, embedder, agent_prompt, query, config, retrieval, tokenizer, filter).await?;
+    answer_prompt(embedder, prompt).await
+}
@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Term frequency saturation.
+const K1: f32 = 1.2;
+/// How strongly scores are normalized by document length.
+const B: f32 = 0.75;
+
+/// Splits text into lowercase alphanumeric terms, so `XJ-200` matches both `xj` and `200`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+/// Inverted index ranking documents against keyword queries with Okapi BM25.
+#[derive(Debug, Clone)]
+pub struct Bm25Index<K> {
+    /// Length in terms and distinct terms of every document.
+    documents: HashMap<K, (usize, Vec<String>)>,
+    /// Term frequency of every document containing a term.
+    postings: HashMap<String, HashMap<K, u32>>,
+    total_length: usize,
+}
+
+impl<K> Default for Bm25Index<K> {
+    fn default() -> Self {
+        Self {
+            documents: HashMap::new(),
+            postings: HashMap::new(),
+            total_length: 0,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Bm25Index<K> {
+    /// Number of indexed documents.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Indexes `text` under `key`, replacing whatever was indexed under it before.
+    pub fn insert(&mut self, key: K, text: &str) {
+        self.remove(&key);
+        let terms = tokenize(text);
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_default() += 1;
+        }
+        self.total_length += terms.len();
+        self.documents
+            .insert(key.clone(), (terms.len(), frequencies.keys().cloned().collect()));
+        for (term, frequency) in frequencies {
+            self.postings.entry(term).or_default().insert(key.clone(), frequency);
+        }
+    }
+
+    /// Removes the document indexed under `key`, if any.
+    pub fn remove(&mut self, key: &K) {
+        let Some((length, terms)) = self.documents.remove(key) else {
+            return;
+        };
+        self.total_length -= length;
+        for term in terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(key);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Returns the `k` best scoring documents containing any term of `query` that pass
+    /// `accept`, best first.
+    pub fn search(&self, query: &str, k: usize, accept: impl Fn(&K) -> bool) -> Vec<(K, f32)> {
+        if self.documents.is_empty() {
+            return vec![];
+        }
+        let count = self.documents.len() as f32;
+        let average_length = self.total_length as f32 / count;
+
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        let mut scores: HashMap<&K, f32> = HashMap::new();
+        for term in &terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+            let matching = posting.len() as f32;
+            let idf = (1.0 + (count - matching + 0.5) / (matching + 0.5)).ln();
+            for (key, &frequency) in posting {
+                if !accept(key) {
+                    continue;
+                }
+                let length = self.documents[key].0 as f32;
+                let frequency = frequency as f32;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(key).or_default() += idf * frequency * (K1 + 1.0) / (frequency + norm);
+            }
+        }
+
+        let mut ranked: Vec<(K, f32)> = scores.into_iter().map(|(key, score)| (key.clone(), score)).collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        ranked.truncate(k);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(vec!["part", "xj", "200", "ünïcode"], tokenize("Part XJ-200: Ünïcode!"));
+    }
+
+    #[test]
+    fn test_bm25_ranking() {
+        let mut index: Bm25Index<usize> = Bm25Index::default();
+        index.insert(0, "The pump uses part XJ-200 and a valve.");
+        index.insert(1, "The pump is blue. The pump is large. The pump is loud.");
+        index.insert(2, "Nothing relevant here.");
+        index.insert(3, "A valve.");
+
+        // The rare identifier outweighs the common word.
+        let ranked = index.search("pump XJ-200", 10, |_| true);
+        assert_eq!(vec![0, 1], ranked.iter().map(|(key, _)| *key).collect::<Vec<_>>());
+        assert!(ranked[0].1 > ranked[1].1);
+
+        // Shorter documents score higher for the same term frequency.
+        let ranked = index.search("valve", 10, |_| true);
+        assert_eq!(vec![3, 0], ranked.iter().map(|(key, _)| *key).collect::<Vec<_>>());
+
+        assert_eq!(vec![0], index.search("valve", 10, |key| *key == 0).into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+
+        index.insert(3, "Replaced text.");
+        index.remove(&0);
+        assert_eq!(3, index.len());
+        assert!(index.search("valve", 10, |_| true).is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::answer::Answer;
+use crate::embedder::Embedder;
+use crate::error::Result;
+use crate::prompt::{build_chat_turn, PromptConfig};
+use crate::retrieval::{retrieve, RetrievalConfig};
+use crate::store::{SearchFilter, VectorStore};
+use crate::tokenizer::{count_tokens, Tokenizer};
+
+use chatgpt::converse::Conversation;
+use chatgpt::types::{ChatMessage, ResponseChunk, Role};
+use futures::StreamExt;
+use std::path::Path;
+
+/// A conversation about the indexed corpus that retrieves fresh sources for every question.
+///
+/// The history keeps the questions and answers only: each turn's sources are sent with that
+/// turn and then dropped, so follow-up questions do not pay for old context.
+pub struct ChatSession {
+    conversation: Conversation,
+    /// Restricts the chunks retrieved for every question.
+    pub filter: SearchFilter,
+    /// How the chunks are ranked against every question.
+    pub retrieval: RetrievalConfig,
+}
+
+impl ChatSession {
+    /// Starts a conversation directed by `agent_prompt`.
+    pub fn new(embedder: &Embedder, agent_prompt: &str, filter: SearchFilter) -> ChatSession {
+        ChatSession {
+            conversation: embedder.client().new_conversation_directed(agent_prompt),
+            filter,
+            retrieval: RetrievalConfig::default(),
+        }
+    }
+
+    /// Restores a conversation saved with [`ChatSession::save`].
+    pub async fn restore(embedder: &Embedder, path: &Path, filter: SearchFilter) -> Result<ChatSession> {
+        Ok(ChatSession {
+            conversation: embedder.client().restore_conversation_json(path).await?,
+            filter,
+            retrieval: RetrievalConfig::default(),
+        })
+    }
+
+    /// Saves the conversation history as JSON.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        Ok(self.conversation.save_history_json(path).await?)
+    }
+
+    /// Messages exchanged so far, starting with the system prompt.
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.conversation.history
+    }
+
+    fn history_tokens(&self, tokenizer: Option<&Tokenizer>) -> usize {
+        self.history()
+            .iter()
+            .map(|message| count_tokens(tokenizer, &message.content))
+            .sum()
+    }
+
+    /// Forgets the oldest questions and answers until the history takes at most half of the
+    /// token budget, leaving the rest for sources.
+    fn trim_history(&mut self, config: &PromptConfig, tokenizer: Option<&Tokenizer>) {
+        while self.conversation.history.len() > 2 && self.history_tokens(tokenizer) > config.max_context_tokens / 2 {
+            self.conversation.history.drain(1..3);
+        }
+    }
+
+    /// Answers `question` from sources retrieved for it, calling `on_delta` with every piece
+    /// of the answer as it streams in.
+    pub async fn ask(
+        &mut self,
+        store: &dyn VectorStore,
+        embedder: &Embedder,
+        question: &str,
+        config: &PromptConfig,
+        tokenizer: Option<&Tokenizer>,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<Answer> {
+        self.trim_history(config, tokenizer);
+        let chunks = retrieve(store, embedder, question, config.top_k, &self.filter, &self.retrieval).await?;
+        let prompt = build_chat_turn(question, &chunks, config, tokenizer, self.history_tokens(tokenizer));
+
+        let mut text = String::new();
+        let sent = self.conversation.send_message_streaming(prompt.messages[0].content.clone()).await;
+        match sent {
+            Ok(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    match chunk {
+                        Ok(ResponseChunk::Content { delta, response_index: 0 }) => {
+                            on_delta(&delta);
+                            text.push_str(&delta);
+                        }
+                        Ok(ResponseChunk::Done) => break,
+                        Ok(_) => {}
+                        Err(e) => {
+                            self.conversation.history.pop();
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                self.conversation.history.pop();
+                return Err(e.into());
+            }
+        }
+
+        // Keep the bare question in the history; its sources were only needed for this turn.
+        if let Some(turn) = self.conversation.history.last_mut() {
+            turn.content = question.to_string();
+        }
+        self.conversation.history.push(ChatMessage {
+            role: Role::Assistant,
+            content: text.clone(),
+        });
+        Ok(Answer { prompt, text })
+    }
+}
@@ -0,0 +1,560 @@
+use crate::tokenizer::Tokenizer;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A piece of a document, with the byte range of the source text it was taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Chunk parameters that cannot produce a valid chunking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    /// `chunk_size` is zero.
+    ZeroChunkSize,
+    /// `chunk_overlap` is not smaller than `chunk_size`, so chunking would never advance.
+    OverlapTooLarge { chunk_size: usize, chunk_overlap: usize },
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkError::ZeroChunkSize => write!(f, "chunk_size must be greater than zero"),
+            ChunkError::OverlapTooLarge { chunk_size, chunk_overlap } => write!(
+                f,
+                "chunk_overlap ({}) must be smaller than chunk_size ({})",
+                chunk_overlap, chunk_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+impl From<ChunkError> for io::Error {
+    fn from(error: ChunkError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// Checks that chunks of `chunk_size` overlapping by `chunk_overlap` make progress.
+pub fn validate_chunk_params(chunk_size: usize, chunk_overlap: usize) -> Result<(), ChunkError> {
+    if chunk_size == 0 {
+        return Err(ChunkError::ZeroChunkSize);
+    }
+    if chunk_overlap >= chunk_size {
+        return Err(ChunkError::OverlapTooLarge { chunk_size, chunk_overlap });
+    }
+    Ok(())
+}
+
+/// Splits a document's text into chunks that are embedded separately.
+///
+/// Every word of the text lands in at least one chunk; only whitespace may be left out.
+pub trait Chunker: Send + Sync {
+    /// Splits `text` into chunks, in document order.
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError>;
+}
+
+/// How documents are split into chunks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    /// Fixed window of words or tokens, ignoring the document's structure.
+    #[default]
+    Window,
+    /// Consecutive sentences, never cutting one in half unless it is oversized.
+    Sentence,
+    /// Consecutive paragraphs, separated by blank lines.
+    Paragraph,
+    /// One chunk per Markdown section, starting at each `#` heading.
+    Heading,
+    /// Splits on paragraphs, then lines, then sentences, then words until pieces fit.
+    Recursive,
+}
+
+/// The `chunking` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkingConfig {
+    /// tiktoken-format vocabulary, e.g. `cl100k_base.tiktoken`. Without one, chunks are sized in words.
+    pub tokenizer: Option<PathBuf>,
+    pub chunk_size: usize,
+    pub chunk_overlap: usize,
+    /// Strategy used for document types without an entry in `strategies`.
+    pub strategy: ChunkStrategy,
+    /// Strategy per document type, keyed by loader name such as `markdown` or `pdf`.
+    pub strategies: BTreeMap<String, ChunkStrategy>,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            tokenizer: None,
+            chunk_size: 400,
+            chunk_overlap: 100,
+            strategy: ChunkStrategy::Window,
+            strategies: BTreeMap::new(),
+        }
+    }
+}
+
+/// Measures text in model tokens when a tokenizer is loaded, in words otherwise.
+#[derive(Clone)]
+pub struct ChunkSize {
+    tokenizer: Option<Arc<Tokenizer>>,
+    /// Maximum size of a chunk.
+    pub max: usize,
+    /// Size of the text repeated at the start of the next chunk.
+    pub overlap: usize,
+}
+
+impl ChunkSize {
+    pub fn new(tokenizer: Option<Arc<Tokenizer>>, max: usize, overlap: usize) -> Result<ChunkSize, ChunkError> {
+        validate_chunk_params(max, overlap)?;
+        Ok(ChunkSize { tokenizer, max, overlap })
+    }
+
+    /// Checks the parameters again, as the fields are public.
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        validate_chunk_params(self.max, self.overlap)
+    }
+
+    /// Size of `text` in the unit chunks are measured in.
+    pub fn measure(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count_tokens(text),
+            None => text.split_whitespace().count(),
+        }
+    }
+
+    /// Byte ranges of the words or tokens of `text`.
+    fn units(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.tokenizer {
+            Some(tokenizer) => {
+                // Tokens may split a multi-byte character; cut before it instead.
+                let mut offsets: Vec<usize> = tokenizer
+                    .encode_with_offsets(text)
+                    .into_iter()
+                    .map(|(_, offset)| floor_char_boundary(text, offset))
+                    .collect();
+                offsets.push(text.len());
+                offsets.windows(2).map(|pair| pair[0]..pair[1]).collect()
+            }
+            None => Regex::new(r"\S+").unwrap().find_iter(text).map(|m| m.range()).collect(),
+        }
+    }
+}
+
+/// Moves `index` back to the closest UTF-8 character boundary of `text`.
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Trims the whitespace around `range` of `text`, returning `None` if nothing is left.
+fn make_chunk(text: &str, range: Range<usize>) -> Option<Chunk> {
+    let slice = &text[range.clone()];
+    let trimmed = slice.trim_start();
+    let start = range.start + (slice.len() - trimmed.len());
+    let trimmed = trimmed.trim_end();
+    if trimmed.is_empty() {
+        return None;
+    }
+    Some(Chunk {
+        text: trimmed.to_string(),
+        start,
+        end: start + trimmed.len(),
+    })
+}
+
+/// Splits `range` of `text` into pieces ending right after each match of `separator`.
+fn split_after(text: &str, range: Range<usize>, separator: &Regex) -> Vec<Range<usize>> {
+    let mut pieces = Vec::new();
+    let mut start = range.start;
+    for found in separator.find_iter(&text[range.clone()]) {
+        let end = range.start + found.end();
+        if end > start {
+            pieces.push(start..end);
+            start = end;
+        }
+    }
+    if start < range.end {
+        pieces.push(start..range.end);
+    }
+    pieces
+}
+
+/// Fixed window over the words or tokens within `range` of `text`.
+fn window(text: &str, range: Range<usize>, size: &ChunkSize) -> Vec<Range<usize>> {
+    let units: Vec<Range<usize>> = size
+        .units(&text[range.clone()])
+        .into_iter()
+        .map(|unit| range.start + unit.start..range.start + unit.end)
+        .collect();
+
+    // The last window is cut short rather than dropped, so the tail is always covered.
+    let mut windows = Vec::new();
+    for i in (0..units.len()).step_by(size.max - size.overlap) {
+        let end = (i + size.max).min(units.len());
+        windows.push(units[i].start..units[end - 1].end);
+        if end == units.len() {
+            break;
+        }
+    }
+    windows
+}
+
+/// Packs consecutive `pieces` into chunks of at most `size.max`, repeating up to `size.overlap`
+/// worth of trailing pieces at the start of the next chunk. Oversized pieces are split by `split`.
+fn pack(
+    text: &str,
+    pieces: Vec<Range<usize>>,
+    size: &ChunkSize,
+    split: &dyn Fn(Range<usize>) -> Vec<Range<usize>>,
+) -> Vec<Range<usize>> {
+    let mut chunks: Vec<Range<usize>> = Vec::new();
+    let mut current: Vec<(Range<usize>, usize)> = Vec::new();
+    let mut current_size = 0;
+
+    let flush = |current: &mut Vec<(Range<usize>, usize)>,
+                 current_size: &mut usize,
+                 chunks: &mut Vec<Range<usize>>,
+                 keep_overlap: bool| {
+        if let (Some(first), Some(last)) = (current.first(), current.last()) {
+            chunks.push(first.0.start..last.0.end);
+        }
+        let mut kept: Vec<(Range<usize>, usize)> = Vec::new();
+        let mut kept_size = 0;
+        if keep_overlap {
+            for (piece, piece_size) in current.iter().rev() {
+                if kept_size + piece_size > size.overlap {
+                    break;
+                }
+                kept_size += piece_size;
+                kept.insert(0, (piece.clone(), *piece_size));
+            }
+        }
+        *current = kept;
+        *current_size = kept_size;
+    };
+
+    for piece in pieces {
+        let piece_size = size.measure(&text[piece.clone()]);
+        if piece_size > size.max {
+            flush(&mut current, &mut current_size, &mut chunks, false);
+            chunks.extend(split(piece));
+            continue;
+        }
+        if current_size + piece_size > size.max {
+            flush(&mut current, &mut current_size, &mut chunks, true);
+            // The overlap alone may leave no room for the new piece.
+            while current_size + piece_size > size.max && !current.is_empty() {
+                current_size -= current.remove(0).1;
+            }
+        }
+        current_size += piece_size;
+        current.push((piece, piece_size));
+    }
+    flush(&mut current, &mut current_size, &mut chunks, false);
+    chunks
+}
+
+fn into_chunks(text: &str, ranges: Vec<Range<usize>>) -> Vec<Chunk> {
+    ranges.into_iter().filter_map(|range| make_chunk(text, range)).collect()
+}
+
+/// Fixed window of `size.max` words or tokens, consecutive chunks sharing `size.overlap`.
+pub struct WindowChunker {
+    pub size: ChunkSize,
+}
+
+impl Chunker for WindowChunker {
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        self.size.validate()?;
+        Ok(into_chunks(text, window(text, 0..text.len(), &self.size)))
+    }
+}
+
+/// Packs whole sentences into chunks.
+pub struct SentenceChunker {
+    pub size: ChunkSize,
+}
+
+impl Chunker for SentenceChunker {
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        self.size.validate()?;
+        let sentence_end = Regex::new(r#"[.!?]+["')\]]*\s+|\n\s*\n"#).unwrap();
+        let sentences = split_after(text, 0..text.len(), &sentence_end);
+        Ok(into_chunks(text, pack(text, sentences, &self.size, &|range| window(text, range, &self.size))))
+    }
+}
+
+/// Packs whole paragraphs into chunks, splitting oversized ones by sentence.
+pub struct ParagraphChunker {
+    pub size: ChunkSize,
+}
+
+impl Chunker for ParagraphChunker {
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        self.size.validate()?;
+        let blank_line = Regex::new(r"\n[ \t]*\n\s*").unwrap();
+        let sentence_end = Regex::new(r#"[.!?]+["')\]]*\s+"#).unwrap();
+        let paragraphs = split_after(text, 0..text.len(), &blank_line);
+        let split_sentences = |range: Range<usize>| {
+            let sentences = split_after(text, range, &sentence_end);
+            pack(text, sentences, &self.size, &|range| window(text, range, &self.size))
+        };
+        Ok(into_chunks(text, pack(text, paragraphs, &self.size, &split_sentences)))
+    }
+}
+
+/// One chunk per Markdown section. Text before the first heading forms its own section and
+/// oversized sections are split by paragraph.
+pub struct HeadingChunker {
+    pub size: ChunkSize,
+}
+
+impl Chunker for HeadingChunker {
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        self.size.validate()?;
+        let heading = Regex::new(r"(?m)^[ ]{0,3}#{1,6}[ \t]").unwrap();
+        let mut starts: Vec<usize> = heading.find_iter(text).map(|m| m.start()).collect();
+        if starts.first() != Some(&0) {
+            starts.insert(0, 0);
+        }
+        starts.push(text.len());
+
+        let paragraphs = ParagraphChunker { size: self.size.clone() };
+        let mut chunks = Vec::new();
+        for section in starts.windows(2).map(|pair| pair[0]..pair[1]) {
+            if self.size.measure(&text[section.clone()]) <= self.size.max {
+                chunks.extend(make_chunk(text, section));
+            } else {
+                chunks.extend(paragraphs.chunk(&text[section.clone()])?.into_iter().map(|chunk| Chunk {
+                    start: section.start + chunk.start,
+                    end: section.start + chunk.end,
+                    ..chunk
+                }));
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+/// Splits on the coarsest separator first and only falls back to finer ones for pieces that
+/// are still too large, ending with a fixed window.
+pub struct RecursiveChunker {
+    pub size: ChunkSize,
+}
+
+impl RecursiveChunker {
+    const SEPARATORS: [&'static str; 4] = [r"\n[ \t]*\n\s*", r"\n", r#"[.!?]+["')\]]*\s+"#, r"\s+"];
+
+    fn split(&self, text: &str, range: Range<usize>, level: usize) -> Vec<Range<usize>> {
+        let Some(separator) = Self::SEPARATORS.get(level) else {
+            return window(text, range, &self.size);
+        };
+        let pieces = split_after(text, range, &Regex::new(separator).unwrap());
+        pack(text, pieces, &self.size, &|range| self.split(text, range, level + 1))
+    }
+}
+
+impl Chunker for RecursiveChunker {
+    fn chunk(&self, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        self.size.validate()?;
+        Ok(into_chunks(text, self.split(text, 0..text.len(), 0)))
+    }
+}
+
+/// Creates the chunker implementing `strategy`.
+pub fn chunker_for_strategy(strategy: ChunkStrategy, size: ChunkSize) -> Box<dyn Chunker> {
+    match strategy {
+        ChunkStrategy::Window => Box::new(WindowChunker { size }),
+        ChunkStrategy::Sentence => Box::new(SentenceChunker { size }),
+        ChunkStrategy::Paragraph => Box::new(ParagraphChunker { size }),
+        ChunkStrategy::Heading => Box::new(HeadingChunker { size }),
+        ChunkStrategy::Recursive => Box::new(RecursiveChunker { size }),
+    }
+}
+
+/// Chunks documents with the strategy configured for their type.
+#[derive(Clone)]
+pub struct TextChunker {
+    size: ChunkSize,
+    strategy: ChunkStrategy,
+    strategies: BTreeMap<String, ChunkStrategy>,
+}
+
+impl TextChunker {
+    pub fn new(size: ChunkSize, strategy: ChunkStrategy) -> TextChunker {
+        TextChunker {
+            size,
+            strategy,
+            strategies: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the chunker described by `config`, loading its tokenizer vocabulary if one is set.
+    pub fn from_config(config: &ChunkingConfig) -> crate::error::Result<TextChunker> {
+        let tokenizer = match &config.tokenizer {
+            Some(path) => Some(Arc::new(Tokenizer::from_file(path).map_err(|e| {
+                crate::error::Error::Config(format!("Unable to load tokenizer {:?}: {}", path, e))
+            })?)),
+            None => None,
+        };
+        Ok(TextChunker {
+            size: ChunkSize::new(tokenizer, config.chunk_size, config.chunk_overlap)?,
+            strategy: config.strategy,
+            strategies: config.strategies.clone(),
+        })
+    }
+
+    /// The loaded tokenizer, if chunks are sized in tokens.
+    pub fn tokenizer(&self) -> Option<&Tokenizer> {
+        self.size.tokenizer.as_deref()
+    }
+
+    /// The strategy used for documents of the given type.
+    pub fn strategy_for(&self, document_type: &str) -> ChunkStrategy {
+        self.strategies.get(document_type).copied().unwrap_or(self.strategy)
+    }
+
+    /// Splits the text of a document of the given type, e.g. `markdown`, into chunks.
+    pub fn chunk(&self, document_type: &str, text: &str) -> Result<Vec<Chunk>, ChunkError> {
+        chunker_for_strategy(self.strategy_for(document_type), self.size.clone()).chunk(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn words(max: usize, overlap: usize) -> ChunkSize {
+        ChunkSize::new(None, max, overlap).unwrap()
+    }
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_offsets_point_into_source() {
+        let text = "# Intro\n\nOne two. Three four five.\n\n## Details\n\nSix seven eight nine. Ten.\n";
+        for strategy in [
+            ChunkStrategy::Window,
+            ChunkStrategy::Sentence,
+            ChunkStrategy::Paragraph,
+            ChunkStrategy::Heading,
+            ChunkStrategy::Recursive,
+        ] {
+            for chunk in chunker_for_strategy(strategy, words(4, 1)).chunk(text).unwrap() {
+                assert_eq!(chunk.text, &text[chunk.start..chunk.end], "{:?}", strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_window_chunker() {
+        let chunks = WindowChunker { size: words(3, 1) }.chunk("A B C D E F G H").unwrap();
+        assert_eq!(vec!["A B C", "C D E", "E F G", "G H"], texts(&chunks));
+    }
+
+    #[test]
+    fn test_structured_chunkers() {
+        let text = "First one. Second one here. Third.\n\nNext paragraph.";
+        let sentences = SentenceChunker { size: words(5, 0) }.chunk(text).unwrap();
+        assert_eq!(vec!["First one. Second one here.", "Third.\n\nNext paragraph."], texts(&sentences));
+
+        let paragraphs = ParagraphChunker { size: words(10, 0) }.chunk(text).unwrap();
+        assert_eq!(vec!["First one. Second one here. Third.\n\nNext paragraph."], texts(&paragraphs));
+
+        let markdown = "Preface.\n# A\nAlpha text.\n## B\nBeta text.";
+        let sections = HeadingChunker { size: words(10, 0) }.chunk(markdown).unwrap();
+        assert_eq!(vec!["Preface.", "# A\nAlpha text.", "## B\nBeta text."], texts(&sections));
+
+        let recursive = RecursiveChunker { size: words(4, 0) }.chunk(text).unwrap();
+        assert_eq!(vec!["First one.", "Second one here. Third.", "Next paragraph."], texts(&recursive));
+    }
+
+    #[test]
+    fn test_strategy_per_document_type() {
+        let config: ChunkingConfig = serde_yaml::from_str("strategy: sentence\nstrategies:\n  markdown: heading\n").unwrap();
+        let chunker = TextChunker::from_config(&config).unwrap();
+        assert_eq!(ChunkStrategy::Heading, chunker.strategy_for("markdown"));
+        assert_eq!(ChunkStrategy::Sentence, chunker.strategy_for("pdf"));
+    }
+
+    #[test]
+    fn test_invalid_chunk_params() {
+        assert_eq!(Some(ChunkError::ZeroChunkSize), ChunkSize::new(None, 0, 0).err());
+        assert!(ChunkSize::new(None, 3, 3).is_err());
+
+        let size = ChunkSize { tokenizer: None, max: 2, overlap: 5 };
+        assert!(WindowChunker { size }.chunk("A B C").is_err());
+
+        let config = ChunkingConfig { chunk_overlap: 400, ..Default::default() };
+        assert!(TextChunker::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_short_document_yields_one_chunk() {
+        let chunks = WindowChunker { size: words(400, 100) }.chunk("Only a few words.").unwrap();
+        assert_eq!(vec!["Only a few words."], texts(&chunks));
+        assert!(WindowChunker { size: words(400, 100) }.chunk("  \n ").unwrap().is_empty());
+    }
+
+    const STRATEGIES: [ChunkStrategy; 5] = [
+        ChunkStrategy::Window,
+        ChunkStrategy::Sentence,
+        ChunkStrategy::Paragraph,
+        ChunkStrategy::Heading,
+        ChunkStrategy::Recursive,
+    ];
+
+    proptest! {
+        #[test]
+        fn prop_every_word_lands_in_a_chunk(
+            text in "([a-zé]{1,6}[.!?]?[ \n]{1,3}|# ){0,60}",
+            max in 1usize..12,
+            overlap_ratio in 0.0f64..1.0,
+            strategy in proptest::sample::select(STRATEGIES.to_vec()),
+        ) {
+            let overlap = (max as f64 * overlap_ratio) as usize;
+            let chunks = chunker_for_strategy(strategy, words(max, overlap)).chunk(&text).unwrap();
+            for chunk in &chunks {
+                prop_assert_eq!(&chunk.text, &text[chunk.start..chunk.end]);
+            }
+            for word in Regex::new(r"\S+").unwrap().find_iter(&text) {
+                prop_assert!(
+                    chunks.iter().any(|chunk| chunk.start <= word.start() && word.end() <= chunk.end),
+                    "{:?} is in no chunk", word.as_str()
+                );
+            }
+        }
+
+        #[test]
+        fn prop_window_chunks_respect_size(
+            text in "([a-z]{1,6} ){0,80}",
+            max in 1usize..12,
+            overlap_ratio in 0.0f64..1.0,
+        ) {
+            let overlap = (max as f64 * overlap_ratio) as usize;
+            let chunks = WindowChunker { size: words(max, overlap) }.chunk(&text).unwrap();
+            prop_assert!(chunks.iter().all(|chunk| chunk.text.split_whitespace().count() <= max));
+        }
+    }
+}
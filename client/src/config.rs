@@ -0,0 +1,96 @@
+use crate::chunker::{validate_chunk_params, ChunkingConfig, TextChunker};
+use crate::embedder::{Embedder, ModelConfig};
+use crate::error::{Error, Result};
+use crate::prompt::PromptConfig;
+use crate::retrieval::RetrievalConfig;
+use crate::store::{open_store, StoreConfig, VectorStore};
+use crate::vector_index::VectorIndexConfig;
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DBSearchConfig {
+    pub agent_prompt: String,
+    pub query: String,
+    #[serde(default)]
+    pub store: StoreConfig,
+    #[serde(default)]
+    pub vector_index: VectorIndexConfig,
+    #[serde(default)]
+    pub model: ModelConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+    #[serde(default)]
+    pub retrieval: RetrievalConfig,
+    /// Maximum number of embedding requests in flight while indexing.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+impl DBSearchConfig {
+    /// Creates the embedder shared by indexing and querying from the `model` section.
+    pub fn embedder(&self) -> Result<Embedder> {
+        Embedder::from_config(&self.model).map_err(|e| {
+            Error::Config(format!("Unable to create ChatGPT client ({}): {}", self.model.api_key_env, e))
+        })
+    }
+
+    /// Creates the chunker described by the `chunking` section.
+    pub fn chunker(&self) -> Result<TextChunker> {
+        TextChunker::from_config(&self.chunking)
+    }
+
+    /// Opens the vector store selected in the `store` section.
+    pub async fn open_store(&self) -> Result<Arc<dyn VectorStore>> {
+        open_store(&self.store, &self.vector_index).await
+    }
+}
+
+/// Load the configuration file.
+pub fn load_config(filename: String) -> Result<DBSearchConfig> {
+    let unreadable = |e: io::Error| Error::Config(format!("Unable to read {}: {}", filename, e));
+    let mut yaml_file = File::open(&filename).map_err(unreadable)?;
+
+    let mut yaml_content = String::new();
+    yaml_file.read_to_string(&mut yaml_content).map_err(unreadable)?;
+
+    //Load the YAML file.
+    let config: DBSearchConfig = serde_yaml::from_str(
+        yaml_content.as_str()
+    ).map_err(|e| Error::Config(format!("Error parsing {}: {}", filename, e)))?;
+
+    // Catch bad chunk sizes before any command starts working.
+    validate_chunk_params(config.chunking.chunk_size, config.chunking.chunk_overlap)
+        .map_err(|e| Error::Config(format!("Invalid chunking in {}: {}", filename, e)))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config_validates_chunking() {
+        let path = std::env::temp_dir().join(format!("dbsearch-config-{}.yaml", std::process::id()));
+        let filename = path.to_string_lossy().into_owned();
+        let load = |chunking: &str| {
+            std::fs::write(&path, format!("agent_prompt: Be brief.\nquery: What?\nchunking:\n{}", chunking)).unwrap();
+            load_config(filename.clone())
+        };
+
+        assert!(load("  chunk_size: 200\n  chunk_overlap: 50\n").is_ok());
+        assert!(matches!(load("  chunk_size: 0\n"), Err(Error::Config(_))));
+        let overlap = load("  chunk_size: 100\n  chunk_overlap: 100\n");
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(overlap, Err(Error::Config(message)) if message.contains("chunk_overlap")));
+    }
+}
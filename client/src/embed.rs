@@ -1,169 +1,464 @@
-use crate::text::*;
 use crate::math::*;
-use crate::pdf::extract_pdf_text;
-use crate::hashes::compute_sha256;
+use crate::loader::loader_for_path;
+use crate::pdf::PageError;
+use crate::chunker::{Chunk, TextChunker};
+use crate::hashes::{compute_sha256, embedding_cache_key};
+use crate::store::{document_key, DocumentStatus, SearchFilter, StoreResult, VectorStore};
+use crate::error::{Error, Result};
+use crate::embedder::Embedder;
+use crate::retrieval::{retrieve, RetrievalConfig};
 
 //use sha2::{Digest, Sha256};
-use std::sync::{Arc, Mutex};
-use std::env;
-use chatgpt::prelude::*;
-use chatgpt::types::*;
-use rayon::prelude::*;
-use tokio::runtime::Runtime;
-use serde_json::*;
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::Semaphore;
 use std::cmp::Ordering;
 use serde::{Serialize, Deserialize};
 //use tokio::time::{delay_for, Duration};
-use std::thread;
-use std::time::Duration;
-use redis::*;
 
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the serialized chunk record. Records without one predate versioning and read as `0`.
+pub const CHUNK_RECORD_VERSION: u32 = 1;
+
+/// Where a chunk was taken from and how it was embedded.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkMetadata {
+    pub version: u32,
+    /// SHA-256 hash of the source document.
+    pub document_id: String,
+    pub path: String,
+    /// 1-based page number, for documents split into pages such as PDFs.
+    pub page: Option<u32>,
+    /// Character offsets of the chunk within the document text.
+    pub char_start: usize,
+    pub char_end: usize,
+    /// Position of the chunk within its document.
+    pub ordinal: usize,
+    /// Embedding model that produced the vector.
+    pub model: String,
+    /// Unix timestamp, in seconds, of when the chunk was embedded.
+    pub created_at: u64,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmbeddingPair {
     pub text: String,
     pub embedding: Vec<f32>,
+    #[serde(default)]
+    pub metadata: ChunkMetadata,
+    /// Similarity to the current query, set on search results only and never stored.
+    #[serde(skip)]
     pub similarity: f32
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct FileHash {
     pub hash: String,
     pub filename: String,
+    /// Embedding model the file was indexed with; empty for files indexed before it was recorded.
+    #[serde(default)]
+    pub model: String,
+}
+
+/// Progress of an embedding run, reported each time a batch of chunks has been stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddingProgress {
+    pub embedded: usize,
+    pub total: usize,
+}
+
+/// Something that happened while indexing, passed to the caller's event callback along with
+/// the path it concerns, so that only the caller decides what to print.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexEvent {
+    /// A directory was walked and this many files were found below it.
+    Found { files: usize },
+    /// A directory below a walked one could not be read and was left out.
+    UnreadableDirectory { error: String },
+    /// The file is already indexed with the current model.
+    Skipped,
+    /// The file was indexed with another model and is embedded again.
+    Reembedding { previous_model: String, model: String },
+    /// Embedding of the file starts.
+    Embedding,
+    /// Where the chunks of the file come from.
+    Chunks { total: usize, stored: usize, cached: usize, to_embed: usize },
+    /// Another batch of chunks of the file has been stored.
+    Progress(EmbeddingProgress),
+    /// A page of the file could not be extracted and was left out.
+    PageSkipped { page: u32, error: String },
+    /// A batch of chunks could not be embedded; the file fails once the other batches finish.
+    BatchFailed { error: String },
+    /// The file could not be indexed and is counted as failed.
+    Failed { error: String },
+    /// The file no longer exists and its chunks were removed.
+    Removed,
+}
+
+/// A chunk returned by a corpus-wide search, annotated with the file it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorpusMatch {
+    pub file: FileHash,
+    /// Position of the chunk within its file, used as the chunk id in citations.
+    pub position: usize,
+    pub pair: EmbeddingPair,
 }
 
 impl EmbeddingPair {
     pub fn new(text: String, embedding: Vec<f32>) -> EmbeddingPair {
         EmbeddingPair {
-            text: text,
-            embedding: embedding,
+            text,
+            embedding,
+            metadata: ChunkMetadata::default(),
             similarity: 0.0,
         }
     }
+
+    pub fn with_metadata(self, metadata: ChunkMetadata) -> EmbeddingPair {
+        EmbeddingPair { metadata, ..self }
+    }
 }
 
+/// Seconds since the Unix epoch.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
 
-pub async fn gpt_get_embeddings(text: &String) -> std::result::Result<Vec<f32>, chatgpt::err::Error> {
-    // Specify the name of the environment variable you want to retrieve
-    let var_name = "OPENAI_API_KEY";
-
-    // Get the value of the environment variable
-    match env::var(var_name) {
-        Ok(val) => {
-            // Creating a new ChatGPT client.
-            // Note that it requires an API key, and uses
-            // tokens from your OpenAI API account balance.
-            let client = ChatGPT::new(val).unwrap();
-            let response: EmbeddingCompletionResponse = client
-                .get_embeddings(&text)
-                .await?;            
-            Ok(response.embeddings().clone())
-        } 
-        Err(_) => {
-            println!("{} is not defined in the environment.", var_name);            
-            Ok(vec![])
+/// Converts the byte offsets of in-order `chunks` into character offsets of `text`.
+fn char_offsets(text: &str, chunks: &[Chunk]) -> Vec<(usize, usize)> {
+    let mut byte_offset = 0;
+    let mut char_offset = 0;
+    let mut advance = |to: usize| {
+        if to >= byte_offset {
+            char_offset += text[byte_offset..to].chars().count();
+        } else {
+            char_offset -= text[to..byte_offset].chars().count();
         }
+        byte_offset = to;
+        char_offset
+    };
+    chunks
+        .iter()
+        .map(|chunk| (advance(chunk.start), advance(chunk.end)))
+        .collect()
+}
+
+
+pub async fn get_embedding_vectors (store: &dyn VectorStore, filename: &str) -> Result<Vec<EmbeddingPair>> {
+    let file_sha256_hash = compute_sha256(filename)?;
+    store.load_file_chunks(&file_sha256_hash).await
+}
+
+/// Checks whether the current content of a file has been fully indexed, from its registry entry
+/// or, for files indexed before the registry existed, from the registered hashes.
+pub async fn is_file_processed (store: &dyn VectorStore, filename: &str) -> Result<bool> {
+    let file_sha256_hash = compute_sha256(filename)?;
+    match store.get_document(&document_key(Path::new(filename))).await? {
+        Some(record) if record.hash == file_sha256_hash => Ok(record.status == DocumentStatus::Complete),
+        _ => store.is_processed(&file_sha256_hash).await,
     }
 }
 
-pub async fn get_embedding_vectors (filename: &str) -> Vec<EmbeddingPair> {
-    let file_sha256_hash = compute_sha256(filename).unwrap();
-    let mut redis_connection: redis::Connection = crate::redis_util::connect_to_redis().await;
-    //let key_name = format!("{}:*", file_sha256_hash);
-    let key_name = format!("{}", file_sha256_hash);
-    let keys: Vec<String> = redis_connection.keys(key_name).unwrap();
-    let mut pair_list: Vec<EmbeddingPair> = Vec::new();
-    for key in keys {
-        let values: Vec<String> = redis_connection.lrange(key.clone(), 0, -1).unwrap();
-        for value in values {
-            let pair: EmbeddingPair = serde_json::from_str(&value).unwrap();
-            pair_list.push(pair);
+/// Embeddings served from the cache and requested from the API while embedding documents.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Chunks whose embedding was cached, or repeated an earlier chunk of the same document.
+    pub hits: usize,
+    /// Chunks whose embedding had to be requested.
+    pub misses: usize,
+}
+
+/// The chunks of a document once every one of them is stored.
+#[derive(Debug, Clone)]
+pub struct EmbeddedDocument {
+    /// Chunks ordered by position.
+    pub pairs: Vec<EmbeddingPair>,
+    pub cache: CacheStats,
+    /// Pages of a paged document that could not be extracted and were left out.
+    pub skipped_pages: Vec<PageError>,
+}
+
+/// Chunks and embeds a document, storing every chunk as soon as its batch is embedded.
+///
+/// Batches are requested concurrently through the shared `embedder`, with `limiter` bounding how
+/// many requests are in flight. Dropping the returned future cancels the run; the file is only
+/// registered as processed once every batch has been stored; otherwise the first error is returned
+/// after the remaining batches have finished.
+pub async fn create_embedding_list (
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    chunker: &TextChunker,
+    filename: &str,
+    limiter: &Semaphore,
+    on_event: &(dyn Fn(IndexEvent) + Sync),
+) -> Result<Vec<EmbeddingPair>> {
+    let file_sha256_hash = compute_sha256(filename)?;
+    let document =
+        resume_embedding_list(store, embedder, chunker, filename, &file_sha256_hash, &[], limiter, on_event).await?;
+    Ok(document.pairs)
+}
+
+/// Like [`create_embedding_list`], but keeps the `stored` chunks of an interrupted run whose
+/// position, text and model still match instead of embedding them again. Chunks are stored under
+/// `file_sha256_hash`, the hash the caller computed for the file.
+///
+/// Chunks are looked up in the store's embedding cache before any are requested, and chunks
+/// repeating earlier text are only requested once; new embeddings are added to the cache.
+#[allow(clippy::too_many_arguments)]
+pub async fn resume_embedding_list (
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    chunker: &TextChunker,
+    filename: &str,
+    file_sha256_hash: &str,
+    stored: &[EmbeddingPair],
+    limiter: &Semaphore,
+    on_event: &(dyn Fn(IndexEvent) + Sync),
+) -> Result<EmbeddedDocument> {
+    let path = Path::new(filename);
+    let loader = loader_for_path(path)?;
+    let document_type = loader.name();
+    let document = loader.load_document(path)?;
+    let document_text = &document.text;
+    let chunks = chunker.chunk(document_type, document_text)?;
+    let offsets = char_offsets(document_text, &chunks);
+    let pages: Vec<Option<u32>> = chunks.iter().map(|chunk| document.page_at(chunk.start)).collect();
+    let text_list: Vec<String> = chunks.into_iter().map(|chunk| chunk.text).collect();
+
+    let file_hash = FileHash {
+        hash: file_sha256_hash.to_string(),
+        filename: filename.to_string(),
+        model: embedder.model().to_string(),
+    };
+    let created_at = unix_timestamp();
+    let new_pair = |ordinal: usize, embedding: Vec<f32>| {
+        let (char_start, char_end) = offsets[ordinal];
+        EmbeddingPair::new(text_list[ordinal].clone(), embedding).with_metadata(ChunkMetadata {
+            version: CHUNK_RECORD_VERSION,
+            document_id: file_hash.hash.clone(),
+            path: file_hash.filename.clone(),
+            page: pages[ordinal],
+            char_start,
+            char_end,
+            ordinal,
+            model: file_hash.model.clone(),
+            created_at,
+        })
+    };
+
+    let mut pair_list: Vec<EmbeddingPair> = stored
+        .iter()
+        .filter(|pair| {
+            let ordinal = pair.metadata.ordinal;
+            pair.metadata.model == file_hash.model && text_list.get(ordinal) == Some(&pair.text)
+        })
+        .cloned()
+        .collect();
+    let mut reused = vec![false; text_list.len()];
+    for pair in &pair_list {
+        reused[pair.metadata.ordinal] = true;
+    }
+    let resumed = pair_list.len();
+
+    // Group the chunks still to embed by cache key, so repeated text is only looked up once.
+    let mut keys: Vec<String> = Vec::new();
+    let mut ordinals_by_key: HashMap<String, Vec<usize>> = HashMap::new();
+    for ordinal in (0..text_list.len()).filter(|&ordinal| !reused[ordinal]) {
+        let key = embedding_cache_key(&file_hash.model, &text_list[ordinal]);
+        let ordinals = ordinals_by_key.entry(key.clone()).or_default();
+        if ordinals.is_empty() {
+            keys.push(key);
         }
+        ordinals.push(ordinal);
     }
-    pair_list
-}
-
-pub async fn is_file_processed (filename: &str) -> bool {
-    let file_sha256_hash = compute_sha256(filename).unwrap();
-    let mut redis_connection: redis::Connection = crate::redis_util::connect_to_redis().await;
-    //let key_name = format!("{}:*", file_sha256_hash);
-    let key_name = format!("{}", file_sha256_hash);
-    let keys: Vec<String> = redis_connection.keys(key_name).unwrap();
-    keys.len() > 0
-}
-
-pub async fn create_embedding_list (filename: &str) -> Vec<EmbeddingPair> {
-    let pdf_text = extract_pdf_text(filename);
-    let mut text_summary: TextSummary = TextSummary::new(pdf_text);
-    let text_list = text_summary.tokenize_words_into_chunks(
-        400, 100
-    );
-    
-    //let key_name = match Path::new(filename).file_name() {
-    //    Some(name) => name.to_str().unwrap().to_string(),
-    //    None => String::from("unknown")
-    //};
-    let file_sha256_hash = compute_sha256(filename).unwrap();
-
-    println!("Getting total of {} text pairs", text_list.len());
-    let pair_list: Arc<Mutex<Vec<EmbeddingPair>>> = Arc::new(Mutex::new(Vec::new()));
-    let redis_connection: redis::Connection = crate::redis_util::connect_to_redis().await;
-    let redis_arc: Arc<Mutex<redis::Connection>> = Arc::from(Mutex::from(redis_connection));            
-    let max_workers = 16; // Define the maximum number of workers (threads) to use
-    //let redis_count: Arc<Mutex<u32>> = Arc::from(Mutex::from(0 as u32));
-    
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(max_workers)
-        .build()
-        .unwrap();
-        
-        pool.install(|| {
-            let rt = Runtime::new().unwrap();
-            text_list.par_iter().for_each(|text_list_item| {
-                thread::sleep(Duration::from_millis(500));
-                if let Ok(embedding) = rt.block_on(gpt_get_embeddings(text_list_item)) {
-                    let new_pair = EmbeddingPair {
-                        text: text_list_item.clone(),
-                        embedding,
-                        similarity: 0.0,
-                    };
-                    pair_list.lock().unwrap().push(new_pair.clone());
-                    let serialized_data : String = serde_json::to_string(&new_pair).unwrap();
-                    //let mut c = redis_count.lock().unwrap();
-                    //*c += 1;
-                    
-                    let key_name = format!("{}", file_sha256_hash);
-
-                    //Let all data exist as an index in a list on the Redis server side that is under a key of
-                    //the SHA-256 hash of the file.
-                    redis_arc.lock().unwrap().lpush::<_,_,()>(key_name.clone(), serialized_data).unwrap();
+
+    let mut cache = CacheStats::default();
+    let mut missing: Vec<String> = Vec::new();
+    for (key, cached) in keys.iter().zip(store.get_cached_embeddings(&keys).await.map_err(Error::store)?) {
+        let ordinals = &ordinals_by_key[key];
+        match cached {
+            Some(embedding) => {
+                for &ordinal in ordinals {
+                    let pair = new_pair(ordinal, embedding.clone());
+                    store.store_chunk(&file_hash, ordinal, &pair).await.map_err(Error::store)?;
+                    pair_list.push(pair);
                 }
-            });
-        });
+                cache.hits += ordinals.len();
+            }
+            None => {
+                cache.hits += ordinals.len() - 1;
+                cache.misses += 1;
+                missing.push(key.clone());
+            }
+        }
+    }
+    let missing_texts: Vec<String> = missing
+        .iter()
+        .map(|key| text_list[ordinals_by_key[key][0]].clone())
+        .collect();
+    on_event(IndexEvent::Chunks {
+        total: text_list.len(),
+        stored: resumed,
+        cached: pair_list.len() - resumed,
+        to_embed: text_list.len() - pair_list.len(),
+    });
 
-    Arc::try_unwrap(pair_list).unwrap().into_inner().unwrap()
+    let batches = embedder.batches(&missing_texts);
+    let mut pending: FuturesUnordered<_> = batches
+        .into_iter()
+        .map(|batch| {
+            let missing = &missing;
+            let missing_texts = &missing_texts;
+            let ordinals_by_key = &ordinals_by_key;
+            let file_hash = &file_hash;
+            let new_pair = &new_pair;
+            async move {
+                // The limiter is never closed, so acquiring it can only wait.
+                let _permit = limiter.acquire().await;
+                let embeddings = embedder.embed_batch(&missing_texts[batch.clone()]).await?;
+
+                let mut pairs: Vec<EmbeddingPair> = Vec::with_capacity(embeddings.len());
+                let mut cached: Vec<(String, Vec<f32>)> = Vec::with_capacity(embeddings.len());
+                for (key, embedding) in missing[batch].iter().zip(embeddings) {
+                    for &ordinal in &ordinals_by_key[key] {
+                        let pair = new_pair(ordinal, embedding.clone());
+                        store.store_chunk(file_hash, ordinal, &pair).await.map_err(Error::store)?;
+                        pairs.push(pair);
+                    }
+                    cached.push((key.clone(), embedding));
+                }
+                store.cache_embeddings(&cached).await.map_err(Error::store)?;
+                Ok::<_, Error>(pairs)
+            }
+        })
+        .collect();
+
+    let mut first_error: Option<Error> = None;
+    while let Some(result) = pending.next().await {
+        match result {
+            Ok(pairs) => {
+                pair_list.extend(pairs);
+                on_event(IndexEvent::Progress(EmbeddingProgress {
+                    embedded: pair_list.len(),
+                    total: text_list.len(),
+                }));
+            }
+            Err(e) => {
+                on_event(IndexEvent::BatchFailed { error: e.to_string() });
+                // A storage failure is returned over an embedding one, as it stops indexing.
+                if first_error.as_ref().is_none_or(|first| !first.is_store() && e.is_store()) {
+                    first_error = Some(e);
+                }
+            }
+        }
+    }
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    store.register_file(&file_hash).await.map_err(Error::store)?;
+    pair_list.sort_by_key(|pair| pair.metadata.ordinal);
+    Ok(EmbeddedDocument {
+        pairs: pair_list,
+        cache,
+        skipped_pages: document.skipped_pages,
+    })
 }
 
+/// Ranks `pairs` by their similarity to `query` and returns the `num_similar_entries` best ones.
 pub async fn search_for_similar_entries(
-    query: String,
+    embedder: &Embedder,
+    query: &str,
     num_similar_entries: usize,
-    pairs: &mut Vec<EmbeddingPair>
-) -> Vec<EmbeddingPair> {
-    let mut result : Vec<EmbeddingPair> = Vec::new();
-    if let Ok(emb) = gpt_get_embeddings(&query).await {
-        for pair in pairs.iter_mut() {
-            pair.similarity = 
-                cosine_similarity(&emb, &pair.embedding);            
+    pairs: &mut [EmbeddingPair]
+) -> chatgpt::Result<Vec<EmbeddingPair>> {
+    let emb = embedder.embed(query).await?;
+    for pair in pairs.iter_mut() {
+        pair.similarity = 
+            cosine_similarity(&emb, &pair.embedding);            
+    }
+
+    // Sort pairs by similarity (higher similarity first)
+    pairs.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap_or(Ordering::Equal));
+
+    // Take the top num_similar_entries pairs
+    Ok(pairs.iter().take(num_similar_entries).cloned().collect())
+}
+
+/// Searches the chunks of every indexed file (or of a named collection) and returns
+/// the `num_similar_entries` best matching ones together with their source file.
+pub async fn search_corpus(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    query: &str,
+    num_similar_entries: usize,
+    collection: Option<&str>,
+    retrieval: &RetrievalConfig,
+) -> StoreResult<Vec<CorpusMatch>> {
+    let mut filter = SearchFilter::default();
+    if let Some(name) = collection {
+        filter.file_hashes = store
+            .list_files(Some(name))
+            .await?
+            .into_iter()
+            .map(|file| file.hash)
+            .collect();
+        if filter.file_hashes.is_empty() {
+            return Ok(vec![]);
         }
+    }
+    retrieve(store, embedder, query, num_similar_entries, &filter, retrieval).await
+}
+
+/// Searches the vector store for the chunks selected by `filter` that are most similar to `query`.
+pub async fn search_similar_chunks(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    query: &str,
+    num_similar_entries: usize,
+    filter: &SearchFilter,
+) -> StoreResult<Vec<CorpusMatch>> {
+    let emb = embedder.embed(query).await?;
+    store.search(&emb, num_similar_entries, filter).await
+}
 
-        // Sort pairs by similarity (higher similarity first)
-        pairs.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap_or(Ordering::Equal));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Take the top num_similar_entries pairs
-        result.extend_from_slice(&pairs[..num_similar_entries]);
+    #[test]
+    fn test_char_offsets() {
+        let text = "héllo wörld again";
+        let chunks = vec![
+            Chunk { text: "héllo wörld".to_string(), start: 0, end: 13 },
+            Chunk { text: "wörld again".to_string(), start: 7, end: 19 },
+        ];
+        assert_eq!(vec![(0, 11), (6, 17)], char_offsets(text, &chunks));
     }
-    result
-}
\ No newline at end of file
+
+    #[test]
+    fn test_chunk_record_serialization() {
+        let mut pair = EmbeddingPair::new("text".to_string(), vec![0.5]).with_metadata(ChunkMetadata {
+            version: CHUNK_RECORD_VERSION,
+            page: Some(3),
+            model: "text-embedding-3-small".to_string(),
+            ..Default::default()
+        });
+        pair.similarity = 0.9;
+
+        let json = serde_json::to_string(&pair).unwrap();
+        assert!(!json.contains("similarity"));
+        let restored: EmbeddingPair = serde_json::from_str(&json).unwrap();
+        assert_eq!(pair.metadata, restored.metadata);
+
+        // Records written before the metadata existed read as version 0.
+        let legacy: EmbeddingPair = serde_json::from_str(r#"{"text":"old","embedding":[1.0],"similarity":0.2}"#).unwrap();
+        assert_eq!(0, legacy.metadata.version);
+    }
+}
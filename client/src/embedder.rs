@@ -0,0 +1,201 @@
+use chatgpt::err::Error;
+use chatgpt::prelude::*;
+use chatgpt::retry::RetryPolicy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+/// The `model` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    /// Environment variable holding the OpenAI API key.
+    pub api_key_env: String,
+    /// Model used to answer questions, e.g. `gpt-4`.
+    pub chat_model: String,
+    /// Model used to embed chunks and queries, e.g. `text-embedding-3-small`.
+    pub embedding_model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    /// Overrides the chat completions endpoint, e.g. to go through a proxy.
+    pub api_url: Option<String>,
+    /// Overrides the embeddings endpoint.
+    pub embed_api_url: Option<String>,
+    /// Timeout of a single HTTP request, in seconds.
+    pub timeout_secs: u64,
+    /// Maximum number of chunks sent in one embeddings request.
+    pub embed_batch_size: usize,
+    /// Approximate maximum number of tokens sent in one embeddings request.
+    pub embed_batch_tokens: usize,
+    /// Attempts per request, including the first one; `1` disables retrying.
+    pub max_attempts: u32,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        let defaults = ModelConfiguration::default();
+        Self {
+            api_key_env: "OPENAI_API_KEY".to_string(),
+            chat_model: defaults.engine.to_string(),
+            embedding_model: defaults.embed_engine.to_string(),
+            temperature: defaults.temperature,
+            max_tokens: defaults.max_tokens,
+            api_url: None,
+            embed_api_url: None,
+            timeout_secs: defaults.timeout.as_secs(),
+            embed_batch_size: defaults.embed_batch_size,
+            embed_batch_tokens: defaults.embed_batch_tokens,
+            max_attempts: defaults.retry_policy.max_attempts,
+        }
+    }
+}
+
+/// Maps a model name onto the matching engine, falling back to a custom engine.
+fn engine_from_name(name: &str) -> ChatGPTEngine {
+    let known = [
+        ChatGPTEngine::Gpt35Turbo,
+        ChatGPTEngine::Gpt35Turbo_0301,
+        ChatGPTEngine::Gpt4,
+        ChatGPTEngine::Gpt4_32k,
+        ChatGPTEngine::Gpt4_0314,
+        ChatGPTEngine::Gpt4_32k_0314,
+        ChatGPTEngine::TextEmbedding3Large,
+        ChatGPTEngine::TextEmbedding3Small,
+    ];
+    match known.into_iter().find(|engine| engine.as_ref() == name) {
+        Some(engine) => engine,
+        None => ChatGPTEngine::Custom(intern(name)),
+    }
+}
+
+/// Returns a `'static` copy of `name`, as custom engines need one. Every distinct name is
+/// leaked once, however many embedders are created.
+fn intern(name: &str) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashMap<String, &'static str>>> = OnceLock::new();
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap_or_else(PoisonError::into_inner);
+    names
+        .entry(name.to_string())
+        .or_insert_with(|| Box::leak(name.to_string().into_boxed_str()))
+}
+
+fn parse_url(value: &str) -> chatgpt::Result<url::Url> {
+    url::Url::from_str(value).map_err(|e| Error::ParsingError(format!("Invalid URL {:?}: {}", value, e)))
+}
+
+impl ModelConfig {
+    /// Builds the client configuration described by this section.
+    pub fn to_model_configuration(&self) -> chatgpt::Result<ModelConfiguration> {
+        let defaults = ModelConfiguration::default();
+        Ok(ModelConfiguration {
+            engine: engine_from_name(&self.chat_model),
+            embed_engine: engine_from_name(&self.embedding_model),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            api_url: match &self.api_url {
+                Some(url) => parse_url(url)?,
+                None => defaults.api_url.clone(),
+            },
+            embed_api_url: match &self.embed_api_url {
+                Some(url) => parse_url(url)?,
+                None => defaults.embed_api_url.clone(),
+            },
+            timeout: Duration::from_secs(self.timeout_secs),
+            embed_batch_size: self.embed_batch_size,
+            embed_batch_tokens: self.embed_batch_tokens,
+            retry_policy: RetryPolicy {
+                max_attempts: self.max_attempts.max(1),
+                ..RetryPolicy::default()
+            },
+            ..defaults
+        })
+    }
+}
+
+/// Turns text into embeddings through one shared, configured ChatGPT client.
+///
+/// Cloning is cheap: clones share the client's connection pool.
+#[derive(Debug, Clone)]
+pub struct Embedder {
+    client: ChatGPT,
+}
+
+impl Embedder {
+    /// Wraps an already configured client.
+    pub fn new(client: ChatGPT) -> Embedder {
+        Embedder { client }
+    }
+
+    /// Creates the client described by `config`, reading the API key from its environment variable.
+    pub fn from_config(config: &ModelConfig) -> chatgpt::Result<Embedder> {
+        let api_key = env::var(&config.api_key_env)?;
+        let client = ChatGPT::new_with_config(api_key, config.to_model_configuration()?)?;
+        Ok(Embedder::new(client))
+    }
+
+    /// The underlying client, also used for chat completions.
+    pub fn client(&self) -> &ChatGPT {
+        &self.client
+    }
+
+    /// Name of the chat model.
+    pub fn chat_model(&self) -> &str {
+        self.client.config.engine.as_ref()
+    }
+
+    /// Name of the embedding model, recorded with every chunk.
+    pub fn model(&self) -> &str {
+        self.client.config.embed_engine.as_ref()
+    }
+
+    /// Splits `texts` into the ranges sent together in one embeddings request.
+    pub fn batches(&self, texts: &[String]) -> Vec<Range<usize>> {
+        self.client.config.embedding_batches(texts)
+    }
+
+    /// Embeds a single text, such as a query.
+    pub async fn embed(&self, text: &str) -> chatgpt::Result<Vec<f32>> {
+        let mut embeddings = self.client.get_embeddings_batch(&[text.to_string()]).await?;
+        embeddings
+            .pop()
+            .filter(|embedding| !embedding.is_empty())
+            .ok_or_else(|| Error::ParsingError("The API returned an empty embedding".to_string()))
+    }
+
+    /// Embeds many texts, returning their embeddings in the same order.
+    pub async fn embed_batch(&self, texts: &[String]) -> chatgpt::Result<Vec<Vec<f32>>> {
+        self.client.get_embeddings_batch(texts).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_config() {
+        let config: ModelConfig = serde_yaml::from_str(
+            "chat_model: gpt-4\nembedding_model: my-embedder\nembed_api_url: http://localhost:8080/v1/embeddings\nmax_attempts: 0\n",
+        )
+        .unwrap();
+        let model = config.to_model_configuration().unwrap();
+        assert_eq!(ChatGPTEngine::Gpt4, model.engine);
+        assert_eq!("my-embedder", model.embed_engine.as_ref());
+        assert_eq!("localhost", model.embed_api_url.host_str().unwrap());
+        assert_eq!(1, model.retry_policy.max_attempts);
+        assert_eq!(ModelConfiguration::default().api_url, model.api_url);
+
+        // A custom name is leaked once, not once per embedder.
+        let again = config.to_model_configuration().unwrap();
+        assert!(std::ptr::eq(model.embed_engine.as_ref(), again.embed_engine.as_ref()));
+
+        let invalid = ModelConfig {
+            api_url: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.to_model_configuration().is_err());
+    }
+}
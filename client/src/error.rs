@@ -0,0 +1,102 @@
+use crate::chunker::ChunkError;
+
+use std::io;
+use thiserror::Error;
+
+/// Errors reported by dbsearch.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Talking to Redis failed.
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    /// Reading or writing a file failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// A PDF could not be parsed or none of its pages could be extracted.
+    #[error("PDF error: {0}")]
+    Pdf(String),
+    /// The embeddings or chat completions API failed.
+    #[error("Embedding error: {0}")]
+    Embedding(#[from] chatgpt::err::Error),
+    /// The configuration file, a command line argument or the environment is invalid.
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// A file, document or collection does not exist.
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// A [`VectorStore`](crate::store::VectorStore) call made while indexing failed.
+    #[error(transparent)]
+    Store(Box<Error>),
+}
+
+/// Result returned throughout dbsearch.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Process exit code reported for this error, distinct for every kind of failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Io(_) => 3,
+            Error::Redis(_) => 4,
+            Error::Pdf(_) => 5,
+            Error::Embedding(_) => 6,
+            Error::NotFound(_) => 7,
+            Error::Store(error) => error.exit_code(),
+        }
+    }
+
+    /// Marks an error returned by a vector store as a storage failure.
+    pub fn store(self) -> Error {
+        match self {
+            Error::Store(_) => self,
+            error => Error::Store(Box::new(error)),
+        }
+    }
+
+    /// Whether this is a storage failure, which stops indexing instead of failing one file.
+    pub fn is_store(&self) -> bool {
+        matches!(self, Error::Store(_))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Error {
+        Error::Io(error.into())
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(error: ChunkError) -> Error {
+        Error::Config(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let errors = [
+            Error::Redis(redis::RedisError::from((redis::ErrorKind::IoError, "refused"))),
+            Error::Io(io::Error::other("disk full")),
+            Error::Pdf("broken".to_string()),
+            Error::Embedding(chatgpt::err::Error::ParsingError("bad".to_string())),
+            Error::Config("bad".to_string()),
+            Error::NotFound("a.pdf".to_string()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(errors.len(), codes.len());
+        assert!(!codes.contains(&0) && !codes.contains(&1));
+
+        assert_eq!("Not found: a.pdf", Error::NotFound("a.pdf".to_string()).to_string());
+        assert!(matches!(Error::from(ChunkError::ZeroChunkSize), Error::Config(_)));
+
+        let store = Error::NotFound("a.pdf".to_string()).store().store();
+        assert!(matches!(&store, Error::Store(inner) if matches!(**inner, Error::NotFound(_))));
+        assert_eq!((7, "Not found: a.pdf".to_string()), (store.exit_code(), store.to_string()));
+    }
+}
@@ -1,10 +1,9 @@
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{self, BufReader, Read};
 
 /// Computes the SHA-256 hash of a file and returns it as a string.
-pub fn compute_sha256(path: &str) -> std::result::Result<String, Box<dyn std::error::Error>> {
+pub fn compute_sha256(path: &str) -> io::Result<String> {
     let file = File::open(path)?;
     let mut reader = BufReader::new(file);
     let mut hasher = Sha256::new();
@@ -21,3 +20,58 @@ pub fn compute_sha256(path: &str) -> std::result::Result<String, Box<dyn std::er
     let result = hasher.finalize();
     Ok(format!("{:x}", result))
 }
+
+/// Key of a chunk in the embedding cache: the SHA-256 hash of the model name and the chunk
+/// text with its whitespace collapsed, so reflowed copies of a chunk share one embedding.
+pub fn embedding_cache_key(model: &str, text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(model.as_bytes());
+    hasher.update([0]);
+    update_words(&mut hasher, text);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Key of a relevance score in the rerank cache: the SHA-256 hash of the chat model name, the
+/// query and the chunk text, each with its whitespace collapsed.
+pub fn rerank_cache_key(model: &str, query: &str, text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(model.as_bytes());
+    hasher.update([0]);
+    update_words(&mut hasher, query);
+    hasher.update([0]);
+    update_words(&mut hasher, text);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Hashes the words of `text` separated by single spaces.
+fn update_words(hasher: &mut Sha256, text: &str) {
+    for (i, word) in text.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_cache_key() {
+        let key = embedding_cache_key("text-embedding-3-small", "Terms and\n  conditions apply.");
+        assert_eq!(key, embedding_cache_key("text-embedding-3-small", " Terms and conditions apply.\n"));
+        assert_ne!(key, embedding_cache_key("text-embedding-3-large", "Terms and conditions apply."));
+        assert_ne!(key, embedding_cache_key("text-embedding-3-small", "Terms and conditions may apply."));
+        assert_eq!(64, key.len());
+    }
+
+    #[test]
+    fn test_rerank_cache_key() {
+        let key = rerank_cache_key("gpt-4", "Which terms  apply?", "Terms and\nconditions apply.");
+        assert_eq!(key, rerank_cache_key("gpt-4", "Which terms apply?", "Terms and conditions apply."));
+        assert_ne!(key, rerank_cache_key("gpt-3.5-turbo", "Which terms apply?", "Terms and conditions apply."));
+        // The separator keeps words from moving between the query and the text.
+        assert_ne!(key, rerank_cache_key("gpt-4", "Which terms apply? Terms", "and conditions apply."));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::embed::*;
+use crate::hashes::compute_sha256;
+use crate::loader::loader_for_path;
+use crate::search::*;
+use crate::store::{document_key, DocumentRecord, DocumentStatus, VectorStore};
+use crate::chunker::TextChunker;
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use std::path::{Path, PathBuf};
+
+/// Totals reported after indexing a directory tree.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSummary {
+    /// Files embedded for the first time.
+    pub embedded: usize,
+    pub skipped: usize,
+    pub unsupported: usize,
+    pub failed: usize,
+    /// Files whose content changed since they were last indexed.
+    #[serde(default)]
+    pub changed: usize,
+    /// Files whose interrupted or failed indexing was finished.
+    #[serde(default)]
+    pub resumed: usize,
+    /// Files that no longer exist and whose chunks were removed.
+    #[serde(default)]
+    pub removed: usize,
+    /// Chunk embeddings served from the embedding cache.
+    #[serde(default)]
+    pub cache_hits: usize,
+    /// Chunk embeddings requested from the API.
+    #[serde(default)]
+    pub cache_misses: usize,
+}
+
+/// What indexing did with a single file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    Embedded,
+    Skipped,
+    Changed,
+    Resumed,
+}
+
+/// Walks `root`, embedding every supported file that has not been processed yet.
+/// When `collection` is given, every supported file found is added to it.
+///
+/// At most `concurrency` embedding requests are in flight at once, and `on_event` is called
+/// with the path and an [`IndexEvent`] whenever something happens to a file or directory. A file
+/// that fails to load or embed is reported and counted, while storage errors abort the walk.
+#[allow(clippy::too_many_arguments)]
+pub async fn index_directory(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    chunker: &TextChunker,
+    root: &Path,
+    options: &WalkOptions,
+    collection: Option<&str>,
+    concurrency: usize,
+    on_event: &(dyn Fn(&str, IndexEvent) + Sync),
+) -> Result<IndexSummary> {
+    let roots = [root.to_path_buf()];
+    index_paths(store, embedder, chunker, &roots, options, collection, concurrency, on_event).await
+}
+
+/// Indexes files and directory trees like [`index_directory`]. Files named directly are
+/// indexed even if `options` would filter them out of a walk.
+///
+/// Every file is tracked in the document registry by path: unchanged files are skipped,
+/// interrupted ones resume where they stopped, and changed ones are re-embedded while their
+/// previous content stays searchable until the new one is complete. Files registered below
+/// one of the directories that no longer exist are removed from the store.
+#[allow(clippy::too_many_arguments)]
+pub async fn index_paths(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    chunker: &TextChunker,
+    paths: &[PathBuf],
+    options: &WalkOptions,
+    collection: Option<&str>,
+    concurrency: usize,
+    on_event: &(dyn Fn(&str, IndexEvent) + Sync),
+) -> Result<IndexSummary> {
+    let mut files: Vec<String> = Vec::new();
+    let mut roots: Vec<String> = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut ctx = FileSearchContext::new();
+            let found = path_walk_files(&mut ctx, path, options)?;
+            for (dir, error) in ctx.skipped_dirs() {
+                on_event(&dir.to_string_lossy(), IndexEvent::UnreadableDirectory { error: error.to_string() });
+            }
+            on_event(&path.to_string_lossy(), IndexEvent::Found { files: found.len() });
+            files.extend(found.into_iter().map(|entry| entry.path));
+            roots.push(document_key(path));
+        } else if path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        } else {
+            return Err(Error::NotFound(path.display().to_string()));
+        }
+    }
+
+    let limiter = Semaphore::new(concurrency.max(1));
+    let mut summary = IndexSummary::default();
+    for file in files {
+        if loader_for_path(Path::new(&file)).is_err() {
+            summary.unsupported += 1;
+            continue;
+        }
+
+        let report = |event: IndexEvent| on_event(&file, event);
+        let (outcome, cache) = match index_file(store, embedder, chunker, &file, collection, &limiter, &report).await {
+            Ok(indexed) => indexed,
+            Err(e) if e.is_store() => return Err(e),
+            Err(e) => {
+                on_event(&file, IndexEvent::Failed { error: e.to_string() });
+                summary.failed += 1;
+                continue;
+            }
+        };
+        summary.cache_hits += cache.hits;
+        summary.cache_misses += cache.misses;
+        match outcome {
+            FileOutcome::Embedded => summary.embedded += 1,
+            FileOutcome::Skipped => summary.skipped += 1,
+            FileOutcome::Changed => summary.changed += 1,
+            FileOutcome::Resumed => summary.resumed += 1,
+        }
+    }
+
+    for record in store.list_documents().await.map_err(Error::store)? {
+        let path = Path::new(&record.path);
+        if roots.iter().any(|root| path.starts_with(root)) && !path.exists() {
+            on_event(&record.path, IndexEvent::Removed);
+            store.remove_document(&record.path).await.map_err(Error::store)?;
+            release_hash(store, &record.hash, &record.path).await?;
+            if let Some(previous_hash) = &record.previous_hash {
+                release_hash(store, previous_hash, &record.path).await?;
+            }
+            summary.removed += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Brings the registry entry and the chunks of one file up to date with its content, adding
+/// it to `collection` if given. Also returns how the embedding cache served the file.
+pub async fn index_file(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    chunker: &TextChunker,
+    file: &str,
+    collection: Option<&str>,
+    limiter: &Semaphore,
+    on_event: &(dyn Fn(IndexEvent) + Sync),
+) -> Result<(FileOutcome, CacheStats)> {
+    let file_sha256_hash = compute_sha256(file)?;
+    let key = document_key(Path::new(file));
+    let known = store.get_document(&key).await.map_err(Error::store)?;
+    let mut record = known.clone().unwrap_or_else(|| DocumentRecord {
+        path: key.clone(),
+        hash: file_sha256_hash.clone(),
+        ..Default::default()
+    });
+    if let Some(name) = collection {
+        if !record.collections.iter().any(|c| c == name) {
+            record.collections.push(name.to_string());
+        }
+    }
+
+    let changed = record.hash != file_sha256_hash;
+    if changed {
+        if record.status == DocumentStatus::Complete {
+            record.previous_hash = Some(record.hash.clone());
+        } else {
+            // The content that was being indexed changed before it was ever complete.
+            release_hash(store, &record.hash, &key).await?;
+        }
+        record.hash = file_sha256_hash.clone();
+        record.status = DocumentStatus::Pending;
+    }
+
+    // Files indexed before models were recorded are assumed to match the current one.
+    match store.get_file(&file_sha256_hash).await.map_err(Error::store)? {
+        Some(indexed) if indexed.model.is_empty() || indexed.model == embedder.model() => {
+            on_event(IndexEvent::Skipped);
+            if record.status != DocumentStatus::Complete {
+                record.chunks = store.load_file_chunks(&file_sha256_hash).await.map_err(Error::store)?.len();
+            }
+            for name in &record.collections {
+                store.add_to_collection(name, &file_sha256_hash).await.map_err(Error::store)?;
+            }
+            complete(store, record).await?;
+            let outcome = if changed { FileOutcome::Changed } else { FileOutcome::Skipped };
+            return Ok((outcome, CacheStats::default()));
+        }
+        Some(indexed) => {
+            on_event(IndexEvent::Reembedding {
+                previous_model: indexed.model.clone(),
+                model: embedder.model().to_string(),
+            });
+            // None of the stored chunks can be reused, and fewer may be written this time. Copies
+            // of the file elsewhere keep their registry entries and collections.
+            if is_shared(store, &file_sha256_hash, &key).await? {
+                store.delete_chunks(&file_sha256_hash).await.map_err(Error::store)?;
+            } else {
+                store.delete_file(&file_sha256_hash).await.map_err(Error::store)?;
+            }
+        }
+        None => {}
+    }
+
+    let outcome = match &known {
+        Some(_) if changed => FileOutcome::Changed,
+        Some(known) if known.status != DocumentStatus::Complete => FileOutcome::Resumed,
+        _ => FileOutcome::Embedded,
+    };
+    record.status = DocumentStatus::Pending;
+    store.put_document(&record).await.map_err(Error::store)?;
+    for name in &record.collections {
+        store.add_to_collection(name, &file_sha256_hash).await.map_err(Error::store)?;
+    }
+
+    on_event(IndexEvent::Embedding);
+    let stored = store.load_file_chunks(&file_sha256_hash).await.map_err(Error::store)?;
+    let cache = match resume_embedding_list(store, embedder, chunker, file, &file_sha256_hash, &stored, limiter, on_event).await {
+        Ok(document) => {
+            for page in &document.skipped_pages {
+                on_event(IndexEvent::PageSkipped { page: page.number, error: page.message.clone() });
+            }
+            record.chunks = document.pairs.len();
+            document.cache
+        }
+        Err(e) => {
+            record.status = DocumentStatus::Failed;
+            record.error = Some(e.to_string());
+            store.put_document(&record).await.map_err(Error::store)?;
+            return Err(e);
+        }
+    };
+    complete(store, record).await?;
+    Ok((outcome, cache))
+}
+
+/// Marks a file's current content as complete, then drops its previous content.
+async fn complete(store: &dyn VectorStore, mut record: DocumentRecord) -> Result<()> {
+    record.status = DocumentStatus::Complete;
+    record.error = None;
+    let previous_hash = record.previous_hash.take();
+    store.put_document(&record).await.map_err(Error::store)?;
+    if let Some(previous_hash) = previous_hash {
+        release_hash(store, &previous_hash, &record.path).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the chunks stored under `file_sha256_hash` unless a registry entry other than the
+/// one of `path` still uses them, e.g. for a copy of the same file elsewhere.
+async fn release_hash(store: &dyn VectorStore, file_sha256_hash: &str, path: &str) -> Result<()> {
+    if !is_shared(store, file_sha256_hash, path).await? {
+        store.delete_file(file_sha256_hash).await.map_err(Error::store)?;
+    }
+    Ok(())
+}
+
+/// Whether a registry entry other than the one of `path` refers to `file_sha256_hash`.
+async fn is_shared(store: &dyn VectorStore, file_sha256_hash: &str, path: &str) -> Result<bool> {
+    Ok(store.list_documents().await.map_err(Error::store)?.iter().any(|record| {
+        record.path != path
+            && (record.hash == file_sha256_hash || record.previous_hash.as_deref() == Some(file_sha256_hash))
+    }))
+}
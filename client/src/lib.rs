@@ -0,0 +1,34 @@
+//! Indexing, storage, search and question answering over a corpus of documents.
+//!
+//! Documents are loaded with [`loader`], split by [`chunker`], embedded through an
+//! [`embedder::Embedder`] and kept in a [`store::VectorStore`]. [`index::index_directory`]
+//! indexes a directory tree, [`embed::search_corpus`] finds the chunks most similar to a query
+//! and [`answer::ask`] answers a question from them.
+
+pub mod math;
+pub mod search;
+pub mod text;
+pub mod chunker;
+pub mod embed;
+pub mod embedder;
+pub mod redis_util;
+pub mod pdf;
+pub mod loader;
+pub mod hashes;
+pub mod vector_index;
+pub mod store;
+pub mod redis_store;
+pub mod local_store;
+pub mod index;
+pub mod prompt;
+pub mod tokenizer;
+pub mod error;
+pub mod config;
+pub mod answer;
+pub mod chat;
+pub mod server;
+pub mod bm25;
+pub mod retrieval;
+pub mod rerank;
+
+pub use error::{Error, Result};
@@ -0,0 +1,440 @@
+use crate::error::{Error, Result};
+use crate::pdf::{extract_pdf_pages, PageError};
+
+use regex::Regex;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of leading bytes inspected when sniffing a file's format.
+const MAGIC_HEADER_LEN: usize = 512;
+
+/// Text of a loaded document and, for paged formats, where each page starts.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoadedDocument {
+    pub text: String,
+    /// Page numbers with the byte offset in `text` where each page starts, in order.
+    pub pages: Vec<(u32, usize)>,
+    /// Pages left out of `text` because they could not be extracted.
+    pub skipped_pages: Vec<PageError>,
+}
+
+impl LoadedDocument {
+    /// Returns the page containing byte `offset` of the text, if the document has pages.
+    pub fn page_at(&self, offset: usize) -> Option<u32> {
+        self.pages
+            .iter()
+            .rev()
+            .find(|(_, start)| *start <= offset)
+            .map(|(number, _)| *number)
+    }
+}
+
+/// Extracts plain text from a document so it can be chunked and embedded.
+pub trait DocumentLoader: Send + Sync {
+    /// Short name of the document format handled by this loader.
+    fn name(&self) -> &'static str;
+
+    /// File extensions (lowercase, without the dot) handled by this loader.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Returns true if the leading bytes of a file identify it as this format.
+    fn matches_magic(&self, _head: &[u8]) -> bool {
+        false
+    }
+
+    /// Reads the document at `path` and returns its text content.
+    fn load(&self, path: &Path) -> Result<String>;
+
+    /// Reads the document at `path`, keeping track of page boundaries for paged formats.
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument> {
+        Ok(LoadedDocument {
+            text: self.load(path)?,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct PdfLoader;
+pub struct TextLoader;
+pub struct MarkdownLoader;
+pub struct HtmlLoader;
+pub struct CsvLoader;
+pub struct JsonLinesLoader;
+
+impl DocumentLoader for PdfLoader {
+    fn name(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn matches_magic(&self, head: &[u8]) -> bool {
+        head.starts_with(b"%PDF-")
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        Ok(self.load_document(path)?.text)
+    }
+
+    /// Pages that fail to extract are skipped and returned in `skipped_pages`; only a PDF
+    /// without any readable page is an error.
+    fn load_document(&self, path: &Path) -> Result<LoadedDocument> {
+        let extracted = extract_pdf_pages(&path.to_string_lossy())?;
+        if extracted.pages.is_empty() && !extracted.errors.is_empty() {
+            return Err(Error::Pdf(format!("No page of {} could be extracted", path.display())));
+        }
+
+        let mut document = LoadedDocument {
+            skipped_pages: extracted.errors,
+            ..Default::default()
+        };
+        for page in extracted.pages {
+            if !document.text.is_empty() {
+                document.text.push_str("\n\n");
+            }
+            document.pages.push((page.number, document.text.len()));
+            document.text.push_str(&page.text);
+        }
+        Ok(document)
+    }
+}
+
+impl DocumentLoader for TextLoader {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "text", "log", "rst"]
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        Ok(read_utf8(path)?)
+    }
+}
+
+impl DocumentLoader for MarkdownLoader {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md", "markdown"]
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        Ok(markdown_to_text(&read_utf8(path)?))
+    }
+}
+
+impl DocumentLoader for HtmlLoader {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm", "xhtml"]
+    }
+
+    fn matches_magic(&self, head: &[u8]) -> bool {
+        let head = String::from_utf8_lossy(head).trim_start().to_lowercase();
+        head.starts_with("<!doctype html") || head.starts_with("<html")
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        Ok(html_to_text(&read_utf8(path)?))
+    }
+}
+
+impl DocumentLoader for CsvLoader {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv", "tsv"]
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        let delimiter = match extension_of(path).as_deref() {
+            Some("tsv") => b'\t',
+            _ => b',',
+        };
+        Ok(csv_to_text(&read_utf8(path)?, delimiter)?)
+    }
+}
+
+impl DocumentLoader for JsonLinesLoader {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["jsonl", "ndjson"]
+    }
+
+    fn load(&self, path: &Path) -> Result<String> {
+        Ok(json_lines_to_text(&read_utf8(path)?)?)
+    }
+}
+
+/// Returns every built-in loader, in the order they are consulted.
+pub fn default_loaders() -> Vec<Box<dyn DocumentLoader>> {
+    vec![
+        Box::new(PdfLoader),
+        Box::new(HtmlLoader),
+        Box::new(MarkdownLoader),
+        Box::new(CsvLoader),
+        Box::new(JsonLinesLoader),
+        Box::new(TextLoader),
+    ]
+}
+
+/// Picks a loader for `path`, first by its magic bytes, then by its extension,
+/// falling back to plain text for any other UTF-8 file.
+pub fn loader_for_path(path: &Path) -> io::Result<Box<dyn DocumentLoader>> {
+    let head = read_head(path)?;
+    let loaders = default_loaders();
+
+    let position = loaders
+        .iter()
+        .position(|l| l.matches_magic(&head))
+        .or_else(|| {
+            let extension = extension_of(path)?;
+            loaders
+                .iter()
+                .position(|l| l.extensions().contains(&extension.as_str()))
+        })
+        .or_else(|| {
+            if looks_like_text(&head) {
+                loaders.iter().position(|l| l.name() == "text")
+            } else {
+                None
+            }
+        });
+
+    position
+        .and_then(|index| loaders.into_iter().nth(index))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("No document loader for {}", path.display()),
+            )
+        })
+}
+
+/// Loads the text of a document using the loader selected for its type.
+pub fn load_document(filename: &str) -> Result<String> {
+    let path = Path::new(filename);
+    loader_for_path(path)?.load(path)
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+fn read_head(path: &Path) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(MAGIC_HEADER_LEN);
+    File::open(path)?
+        .take(MAGIC_HEADER_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+fn read_utf8(path: &Path) -> io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    !head.contains(&0) && std::str::from_utf8(head).is_ok()
+}
+
+/// Strips Markdown syntax, keeping the readable text and `#` heading markers.
+pub fn markdown_to_text(markdown: &str) -> String {
+    let image = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    let link = Regex::new(r"\[([^\]]*)\]\([^)]*\)").unwrap();
+    let heading = Regex::new(r"(?m)^[ ]{0,3}(#{1,6})[ \t]+").unwrap();
+    let bare_heading = Regex::new(r"(?m)^[ ]{0,3}(#{1,6})[ \t]*$").unwrap();
+    // Only paired delimiters around non-space text, closed by the nearest delimiter, so
+    // `a * b` and `**a** and **b**` come out right.
+    let emphasis = [
+        Regex::new(r"\*\*([^*\s](?:[^*]*[^*\s])?)\*\*").unwrap(),
+        Regex::new(r"~~([^~\s](?:[^~]*[^~\s])?)~~").unwrap(),
+        Regex::new(r"`([^`\n]+)`").unwrap(),
+    ];
+    // Single `*` and underscores only delimit emphasis outside words, so `2*3` and `max_size`
+    // survive. Underscores may appear inside, as in `_min_size_`.
+    let within_words = [
+        Regex::new(r"(^|[^\w*])\*([^*\s](?:[^*]*[^*\s])?)\*([^\w*]|$)").unwrap(),
+        Regex::new(r"(^|\W)__([^_\s](?:.*?[^_\s])??)__(\W|$)").unwrap(),
+        Regex::new(r"(^|\W)_([^_\s](?:.*?[^_\s])??)_(\W|$)").unwrap(),
+    ];
+    let fence = Regex::new(r"(?m)^\s*```.*$").unwrap();
+    let quote = Regex::new(r"(?m)^\s*>\s?").unwrap();
+
+    let text = fence.replace_all(markdown, "");
+    let text = image.replace_all(&text, "$1");
+    let text = link.replace_all(&text, "$1");
+    // Heading markers are kept, normalized, so chunkers can split on sections.
+    let text = bare_heading.replace_all(&text, "$1");
+    let text = heading.replace_all(&text, "$1 ");
+    let mut text = quote.replace_all(&text, "").into_owned();
+    for pattern in &emphasis {
+        text = pattern.replace_all(&text, "$1").into_owned();
+    }
+    for pattern in &within_words {
+        // Neighbours sharing the character between them cannot match in the same pass.
+        loop {
+            let stripped = pattern.replace_all(&text, "$1$2$3").into_owned();
+            if stripped == text {
+                break;
+            }
+            text = stripped;
+        }
+    }
+    text
+}
+
+/// Removes tags, scripts and styles from HTML and decodes common entities.
+pub fn html_to_text(html: &str) -> String {
+    let hidden = Regex::new(r"(?is)<(script|style|head)[^>]*>.*?</(script|style|head)>").unwrap();
+    let comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    let block = Regex::new(r"(?i)</?(p|div|br|li|tr|h[1-6]|section|article)[^>]*>").unwrap();
+    let tag = Regex::new(r"(?s)<[^>]*>").unwrap();
+
+    let text = hidden.replace_all(html, " ");
+    let text = comment.replace_all(&text, " ");
+    let text = block.replace_all(&text, "\n");
+    let text = tag.replace_all(&text, "");
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Renders each CSV record as a line of `header: value` pairs.
+pub fn csv_to_text(csv_data: &str, delimiter: u8) -> io::Result<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(csv_data.as_bytes());
+    let headers = reader.headers().map_err(io::Error::other)?.clone();
+
+    let mut lines = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(io::Error::other)?;
+        let fields: Vec<String> = record
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(i, value)| match headers.get(i) {
+                Some(header) => format!("{}: {}", header, value),
+                None => value.to_string(),
+            })
+            .collect();
+        lines.push(fields.join("; "));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Renders each JSON object on its own line as `key: value` pairs.
+pub fn json_lines_to_text(json_lines: &str) -> io::Result<String> {
+    let mut lines = Vec::new();
+    for line in json_lines.lines().filter(|l| !l.trim().is_empty()) {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        let mut fields = Vec::new();
+        flatten_json("", &value, &mut fields);
+        lines.push(fields.join("; "));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn flatten_json(prefix: &str, value: &serde_json::Value, fields: &mut Vec<String>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&join(key), value, fields);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (i, value) in items.iter().enumerate() {
+                flatten_json(&join(&i.to_string()), value, fields);
+            }
+        }
+        serde_json::Value::Null => {}
+        serde_json::Value::String(s) if prefix.is_empty() => fields.push(s.clone()),
+        serde_json::Value::String(s) => fields.push(format!("{}: {}", prefix, s)),
+        other if prefix.is_empty() => fields.push(other.to_string()),
+        other => fields.push(format!("{}: {}", prefix, other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_text() {
+        let text = markdown_to_text("# Title\n\nSome **bold** and a [link](http://x).");
+        assert_eq!("# Title\n\nSome bold and a link.", text);
+
+        let text = markdown_to_text("Set *max_size* to `a * b` or _min_size_, ~~not~~ __this__.");
+        assert_eq!("Set max_size to a * b or min_size, not this.", text);
+        assert_eq!("a * b and 2*3 = x_1", markdown_to_text("a * b and 2*3 = x_1"));
+        assert_eq!("a and b", markdown_to_text("**a** and **b**"));
+        assert_eq!("x or y", markdown_to_text("~~x~~ or ~~y~~"));
+        assert_eq!("a b", markdown_to_text("_a_ _b_"));
+        assert_eq!("2*3 = x*4", markdown_to_text("2*3 = x*4"));
+        assert_eq!("a b c and snake_case_name", markdown_to_text("*a* *b* *c* and snake_case_name"));
+
+        // Heading markers need whitespace after them.
+        let text = markdown_to_text("#include <x.h>\n#hashtag\n#!/bin/sh\n##   Section\n#\n  ##  \nEnd");
+        assert_eq!("#include <x.h>\n#hashtag\n#!/bin/sh\n## Section\n#\n##\nEnd", text);
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let text = html_to_text("<html><head><title>x</title></head><body><p>A &amp; B</p><script>y()</script></body></html>");
+        assert_eq!("A & B", text.trim());
+    }
+
+    #[test]
+    fn test_csv_to_text() {
+        let text = csv_to_text("name,size\nbolt,M4\nnut,\n", b',').unwrap();
+        assert_eq!("name: bolt; size: M4\nname: nut", text);
+    }
+
+    #[test]
+    fn test_json_lines_to_text() {
+        let text = json_lines_to_text("{\"a\": \"x\", \"b\": {\"c\": 1}}\n\n[\"y\"]\n").unwrap();
+        assert_eq!("a: x; b.c: 1\n0: y", text);
+    }
+
+    #[test]
+    fn test_page_at() {
+        let document = LoadedDocument {
+            text: "one\n\ntwo\n\nfour".to_string(),
+            pages: vec![(1, 0), (2, 5), (4, 10)],
+            ..Default::default()
+        };
+        assert_eq!(Some(1), document.page_at(3));
+        assert_eq!(Some(2), document.page_at(5));
+        assert_eq!(Some(4), document.page_at(12));
+        assert_eq!(None, LoadedDocument::default().page_at(0));
+    }
+}
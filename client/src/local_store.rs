@@ -0,0 +1,725 @@
+use crate::bm25::Bm25Index;
+use crate::embed::{ChunkMetadata, CorpusMatch, EmbeddingPair, FileHash};
+use crate::error::Error;
+use crate::math::cosine_similarity;
+use crate::store::*;
+
+use async_trait::async_trait;
+use memmap2::Mmap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Flat file of little-endian f32 vectors, one row per chunk.
+const VECTORS_FILE: &str = "vectors.f32";
+/// Sidecar of JSON lines describing each row of the vectors file.
+const CHUNKS_FILE: &str = "chunks.jsonl";
+/// Registered files, collections, the document registry and the embedding width.
+const MANIFEST_FILE: &str = "manifest.json";
+/// JSON lines of cached embeddings, keyed by model and chunk text.
+const CACHE_FILE: &str = "embedding-cache.jsonl";
+/// JSON lines of cached relevance scores, keyed by model, query and chunk text.
+const SCORE_CACHE_FILE: &str = "rerank-cache.jsonl";
+
+/// Metadata of one row in the vectors file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChunkRow {
+    file_hash: String,
+    filename: String,
+    position: usize,
+    text: String,
+    #[serde(default)]
+    metadata: ChunkMetadata,
+}
+
+/// One line of the embedding cache file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    embedding: Vec<f32>,
+}
+
+/// One line of the rerank cache file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScoreEntry {
+    key: String,
+    score: f32,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Manifest {
+    dimensions: Option<usize>,
+    files: BTreeMap<String, FileHash>,
+    collections: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    documents: BTreeMap<String, DocumentRecord>,
+}
+
+struct LocalState {
+    manifest: Manifest,
+    rows: Vec<ChunkRow>,
+    /// Latest row written for each (file hash, position), so rewritten chunks replace older ones.
+    live: HashMap<(String, usize), usize>,
+    /// Keyword index over the text of the live rows, rebuilt whenever the store is opened.
+    keywords: Bm25Index<(String, usize)>,
+    cache: HashMap<String, Vec<f32>>,
+    scores: HashMap<String, f32>,
+}
+
+/// Reads a JSON lines cache file. A line cut short by a crash only costs a request to the API,
+/// so invalid lines are skipped.
+fn read_cache<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let mut entries = Vec::new();
+    if path.exists() {
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Ok(entry) = serde_json::from_str(&line?) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Appends entries to a JSON lines cache file.
+fn append_cache<T: Serialize>(path: &Path, entries: &[T]) -> io::Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&serde_json::to_string(entry)?);
+        lines.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(lines.as_bytes())
+}
+
+/// Builds the keyword index over the live rows.
+fn index_keywords(rows: &[ChunkRow], live: &HashMap<(String, usize), usize>) -> Bm25Index<(String, usize)> {
+    let mut keywords = Bm25Index::default();
+    for (key, &row) in live {
+        keywords.insert(key.clone(), &rows[row].text);
+    }
+    keywords
+}
+
+/// Describes the file a row belongs to.
+fn row_file(row: &ChunkRow) -> FileHash {
+    FileHash {
+        hash: row.file_hash.clone(),
+        filename: row.filename.clone(),
+        model: row.metadata.model.clone(),
+    }
+}
+
+/// Vector store kept in a local directory, needing no external services.
+///
+/// Vectors are appended to a flat file that is memory-mapped for searches, while
+/// chunk text and file registrations live in small sidecar files. Every operation runs on
+/// tokio's blocking thread pool, since most of them read or write those files.
+pub struct LocalStore {
+    shared: Arc<Shared>,
+}
+
+/// The store directory and the state kept in memory for it, shared with blocking tasks.
+struct Shared {
+    dir: PathBuf,
+    state: Mutex<LocalState>,
+}
+
+impl LocalStore {
+    /// Opens the store in `dir`, creating the directory if needed.
+    pub fn open(dir: &Path) -> io::Result<LocalStore> {
+        fs::create_dir_all(dir)?;
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest: Manifest = if manifest_path.exists() {
+            serde_json::from_slice(&fs::read(&manifest_path)?)?
+        } else {
+            Manifest::default()
+        };
+
+        // Byte offset after each row, to cut the file back to the rows that have a vector.
+        let mut rows: Vec<ChunkRow> = Vec::new();
+        let mut row_ends: Vec<u64> = Vec::new();
+        let chunks_path = dir.join(CHUNKS_FILE);
+        let chunks = if chunks_path.exists() { fs::read(&chunks_path)? } else { vec![] };
+        let mut offset = 0;
+        for line in chunks.split_inclusive(|&b| b == b'\n') {
+            offset += line.len() as u64;
+            // A last line without its newline was cut short by a crash.
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                rows.push(serde_json::from_slice(line)?);
+                row_ends.push(offset);
+            }
+        }
+
+        // A row only counts once both its metadata and its vector have been fully written.
+        let dimensions = manifest.dimensions.unwrap_or(0);
+        let vectors_path = dir.join(VECTORS_FILE);
+        let vectors_len = fs::metadata(&vectors_path).map(|m| m.len()).unwrap_or(0);
+        let vector_rows = match dimensions {
+            0 => 0,
+            dimensions => (vectors_len / (dimensions as u64 * 4)) as usize,
+        };
+        rows.truncate(vector_rows);
+        // Cut off whatever a failed write left behind, so that appends stay aligned.
+        let chunks_end = rows.len().checked_sub(1).map_or(0, |last| row_ends[last]);
+        if chunks_end < chunks.len() as u64 {
+            OpenOptions::new().write(true).open(&chunks_path)?.set_len(chunks_end)?;
+        }
+        let vectors_end = (rows.len() * dimensions * 4) as u64;
+        if vectors_end < vectors_len {
+            OpenOptions::new().write(true).open(&vectors_path)?.set_len(vectors_end)?;
+        }
+
+        let live = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| ((row.file_hash.clone(), row.position), i))
+            .collect();
+        let keywords = index_keywords(&rows, &live);
+
+        let cache = read_cache::<CacheEntry>(&dir.join(CACHE_FILE))?
+            .into_iter()
+            .map(|entry| (entry.key, entry.embedding))
+            .collect();
+        let scores = read_cache::<ScoreEntry>(&dir.join(SCORE_CACHE_FILE))?
+            .into_iter()
+            .map(|entry| (entry.key, entry.score))
+            .collect();
+
+        Ok(LocalStore {
+            shared: Arc::new(Shared {
+                dir: dir.to_path_buf(),
+                state: Mutex::new(LocalState { manifest, rows, live, keywords, cache, scores }),
+            }),
+        })
+    }
+
+    /// Runs `op` on a blocking thread with the state locked. A panic in an earlier operation
+    /// may have left the state half updated, so a poisoned lock fails every later one.
+    async fn with_state<T, F>(&self, op: F) -> StoreResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Shared, &mut LocalState) -> StoreResult<T> + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut state = shared
+                .state
+                .lock()
+                .map_err(|_| io::Error::other("The local store was left inconsistent by an earlier failure"))?;
+            op(&shared, &mut state)
+        })
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))?
+    }
+}
+
+impl Shared {
+    fn save_manifest(&self, manifest: &Manifest) -> io::Result<()> {
+        let tmp_path = self.dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&tmp_path, serde_json::to_vec_pretty(manifest)?)?;
+        fs::rename(tmp_path, self.dir.join(MANIFEST_FILE))
+    }
+
+    fn map_vectors(&self) -> io::Result<Option<Mmap>> {
+        let path = self.dir.join(VECTORS_FILE);
+        if !path.exists() || fs::metadata(&path)?.len() == 0 {
+            return Ok(None);
+        }
+        let file = File::open(path)?;
+        // SAFETY: the vectors file is only ever appended to while the state lock is held.
+        Ok(Some(unsafe { Mmap::map(&file)? }))
+    }
+
+    /// Rewrites the vectors and chunks files with only the live rows passing `keep`, which
+    /// also drops rows superseded by rewritten chunks. Returns the number of live rows removed.
+    fn compact(&self, state: &mut LocalState, keep: impl Fn(&ChunkRow) -> bool) -> io::Result<usize> {
+        let dimensions = state.manifest.dimensions.unwrap_or(0);
+        let mut live: Vec<usize> = state.live.values().copied().collect();
+        live.sort_unstable();
+
+        let mut rows: Vec<ChunkRow> = Vec::new();
+        let mut vector_bytes: Vec<u8> = Vec::new();
+        let mut lines = String::new();
+        if let Some(vectors) = self.map_vectors()? {
+            for &index in &live {
+                let row = &state.rows[index];
+                if !keep(row) {
+                    continue;
+                }
+                vector_bytes.extend_from_slice(&vectors[index * dimensions * 4..(index + 1) * dimensions * 4]);
+                lines.push_str(&serde_json::to_string(row)?);
+                lines.push('\n');
+                rows.push(row.clone());
+            }
+        }
+        let removed = live.len() - rows.len();
+
+        let tmp_path = |name: &str| self.dir.join(format!("{}.tmp", name));
+        fs::write(tmp_path(VECTORS_FILE), &vector_bytes)?;
+        fs::write(tmp_path(CHUNKS_FILE), lines)?;
+        fs::rename(tmp_path(VECTORS_FILE), self.dir.join(VECTORS_FILE))?;
+        fs::rename(tmp_path(CHUNKS_FILE), self.dir.join(CHUNKS_FILE))?;
+
+        state.live = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| ((row.file_hash.clone(), row.position), i))
+            .collect();
+        state.keywords = index_keywords(&rows, &state.live);
+        state.rows = rows;
+        Ok(removed)
+    }
+}
+
+fn read_vector(vectors: &[u8], row: usize, dimensions: usize) -> Vec<f32> {
+    let start = row * dimensions * 4;
+    vectors[start..start + dimensions * 4]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+    async fn store_chunk(&self, file: &FileHash, position: usize, pair: &EmbeddingPair) -> StoreResult<()> {
+        let (file, pair) = (file.clone(), pair.clone());
+        self.with_state(move |store, state| {
+            match state.manifest.dimensions {
+                Some(dimensions) if dimensions != pair.embedding.len() => {
+                    // The configured embedding model differs from the one the store was built with.
+                    return Err(Error::Config(format!(
+                        "Embedding has {} dimensions but the store holds {}",
+                        pair.embedding.len(),
+                        dimensions
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    state.manifest.dimensions = Some(pair.embedding.len());
+                    store.save_manifest(&state.manifest)?;
+                }
+            }
+
+            let row = ChunkRow {
+                file_hash: file.hash.clone(),
+                filename: file.filename.clone(),
+                position,
+                text: pair.text.clone(),
+                metadata: pair.metadata.clone(),
+            };
+            let mut line = serde_json::to_string(&row)?;
+            line.push('\n');
+
+            let bytes: Vec<u8> = pair.embedding.iter().flat_map(|v| v.to_le_bytes()).collect();
+            let path = |name: &str| store.dir.join(name);
+            let length = |name: &str| fs::metadata(path(name)).map(|m| m.len()).unwrap_or(0);
+            let (vectors_len, chunks_len) = (length(VECTORS_FILE), length(CHUNKS_FILE));
+            let append = |name: &str, bytes: &[u8]| {
+                OpenOptions::new().create(true).append(true).open(path(name))?.write_all(bytes)
+            };
+            if let Err(e) = append(VECTORS_FILE, &bytes).and_then(|_| append(CHUNKS_FILE, line.as_bytes())) {
+                // Roll both files back so that later rows stay aligned with their vectors.
+                let truncate = |name: &str, len: u64| OpenOptions::new().write(true).open(path(name))?.set_len(len);
+                let _ = truncate(VECTORS_FILE, vectors_len);
+                let _ = truncate(CHUNKS_FILE, chunks_len);
+                return Err(e.into());
+            }
+
+            let index = state.rows.len();
+            state.keywords.insert((file.hash.clone(), position), &row.text);
+            state.rows.push(row);
+            state.live.insert((file.hash, position), index);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn register_file(&self, file: &FileHash) -> StoreResult<()> {
+        let file = file.clone();
+        self.with_state(move |store, state| {
+            state.manifest.files.insert(file.hash.clone(), file);
+            store.save_manifest(&state.manifest)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_processed(&self, file_sha256_hash: &str) -> StoreResult<bool> {
+        let hash = file_sha256_hash.to_string();
+        self.with_state(move |_, state| Ok(state.manifest.files.contains_key(&hash))).await
+    }
+
+    async fn get_file(&self, file_sha256_hash: &str) -> StoreResult<Option<FileHash>> {
+        let hash = file_sha256_hash.to_string();
+        self.with_state(move |_, state| Ok(state.manifest.files.get(&hash).cloned())).await
+    }
+
+    async fn load_file_chunks(&self, file_sha256_hash: &str) -> StoreResult<Vec<EmbeddingPair>> {
+        let hash = file_sha256_hash.to_string();
+        self.with_state(move |store, state| {
+            let (Some(vectors), Some(dimensions)) = (store.map_vectors()?, state.manifest.dimensions) else {
+                return Ok(vec![]);
+            };
+
+            let mut chunks: Vec<(usize, EmbeddingPair)> = state
+                .live
+                .iter()
+                .filter(|((file_hash, _), _)| *file_hash == hash)
+                .map(|((_, position), &row)| {
+                    let pair = EmbeddingPair::new(
+                        state.rows[row].text.clone(),
+                        read_vector(&vectors, row, dimensions),
+                    )
+                    .with_metadata(state.rows[row].metadata.clone());
+                    (*position, pair)
+                })
+                .collect();
+            chunks.sort_by_key(|(position, _)| *position);
+            Ok(chunks.into_iter().map(|(_, pair)| pair).collect())
+        })
+        .await
+    }
+
+    async fn list_files(&self, collection: Option<&str>) -> StoreResult<Vec<FileHash>> {
+        let collection = collection.map(str::to_string);
+        self.with_state(move |_, state| {
+            let manifest = &state.manifest;
+            let mut files: Vec<FileHash> = match collection {
+                Some(name) => manifest
+                    .collections
+                    .get(&name)
+                    .map(|hashes| hashes.iter().filter_map(|h| manifest.files.get(h).cloned()).collect())
+                    .unwrap_or_default(),
+                None => manifest.files.values().cloned().collect(),
+            };
+            files.sort_by(|a, b| a.filename.cmp(&b.filename));
+            Ok(files)
+        })
+        .await
+    }
+
+    async fn add_to_collection(&self, collection: &str, file_sha256_hash: &str) -> StoreResult<()> {
+        let (collection, hash) = (collection.to_string(), file_sha256_hash.to_string());
+        self.with_state(move |store, state| {
+            state.manifest.collections.entry(collection).or_default().insert(hash);
+            store.save_manifest(&state.manifest)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_file(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        let hash = file_sha256_hash.to_string();
+        self.with_state(move |store, state| {
+            // Unregister first so a partially deleted file is never reported as processed.
+            state.manifest.files.remove(&hash);
+            for members in state.manifest.collections.values_mut() {
+                members.remove(&hash);
+            }
+            state.manifest.documents.retain(|_, record| record.hash != hash);
+            store.save_manifest(&state.manifest)?;
+            Ok(store.compact(state, |row| row.file_hash != hash)?)
+        })
+        .await
+    }
+
+    async fn delete_chunks(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        let hash = file_sha256_hash.to_string();
+        self.with_state(move |store, state| Ok(store.compact(state, |row| row.file_hash != hash)?)).await
+    }
+
+    async fn get_document(&self, path: &str) -> StoreResult<Option<DocumentRecord>> {
+        let path = path.to_string();
+        self.with_state(move |_, state| Ok(state.manifest.documents.get(&path).cloned())).await
+    }
+
+    async fn put_document(&self, record: &DocumentRecord) -> StoreResult<()> {
+        let record = record.clone();
+        self.with_state(move |store, state| {
+            state.manifest.documents.insert(record.path.clone(), record);
+            store.save_manifest(&state.manifest)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_document(&self, path: &str) -> StoreResult<()> {
+        let path = path.to_string();
+        self.with_state(move |store, state| {
+            if state.manifest.documents.remove(&path).is_some() {
+                store.save_manifest(&state.manifest)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_documents(&self) -> StoreResult<Vec<DocumentRecord>> {
+        self.with_state(|_, state| Ok(state.manifest.documents.values().cloned().collect())).await
+    }
+
+    async fn get_cached_embeddings(&self, keys: &[String]) -> StoreResult<Vec<Option<Vec<f32>>>> {
+        let keys = keys.to_vec();
+        self.with_state(move |_, state| Ok(keys.iter().map(|key| state.cache.get(key).cloned()).collect()))
+            .await
+    }
+
+    async fn cache_embeddings(&self, entries: &[(String, Vec<f32>)]) -> StoreResult<()> {
+        let entries = entries.to_vec();
+        self.with_state(move |store, state| {
+            let mut added = Vec::new();
+            for (key, embedding) in entries {
+                if state.cache.insert(key.clone(), embedding.clone()).is_none() {
+                    added.push(CacheEntry { key, embedding });
+                }
+            }
+            append_cache(&store.dir.join(CACHE_FILE), &added)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_cached_scores(&self, keys: &[String]) -> StoreResult<Vec<Option<f32>>> {
+        let keys = keys.to_vec();
+        self.with_state(move |_, state| Ok(keys.iter().map(|key| state.scores.get(key).copied()).collect()))
+            .await
+    }
+
+    async fn cache_scores(&self, entries: &[(String, f32)]) -> StoreResult<()> {
+        let entries = entries.to_vec();
+        self.with_state(move |store, state| {
+            let mut added = Vec::new();
+            for (key, score) in entries {
+                if state.scores.insert(key.clone(), score).is_none() {
+                    added.push(ScoreEntry { key, score });
+                }
+            }
+            append_cache(&store.dir.join(SCORE_CACHE_FILE), &added)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn stats(&self) -> StoreResult<StoreStats> {
+        self.with_state(|_, state| {
+            Ok(StoreStats {
+                files: state.manifest.files.len(),
+                chunks: state.live.len(),
+                collections: state.manifest.collections.len(),
+            })
+        })
+        .await
+    }
+
+    async fn search(&self, embedding: &[f32], k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        let (embedding, filter) = (embedding.to_vec(), filter.clone());
+        self.with_state(move |store, state| {
+            let (Some(vectors), Some(dimensions)) = (store.map_vectors()?, state.manifest.dimensions) else {
+                return Ok(vec![]);
+            };
+
+            let mut matches: Vec<CorpusMatch> = Vec::new();
+            for &row_index in state.live.values() {
+                let row = &state.rows[row_index];
+                let file = row_file(row);
+                if !filter.matches(&file, row.position) {
+                    continue;
+                }
+                let mut pair = EmbeddingPair::new(row.text.clone(), read_vector(&vectors, row_index, dimensions))
+                    .with_metadata(row.metadata.clone());
+                pair.similarity = cosine_similarity(&embedding, &pair.embedding);
+                matches.push(CorpusMatch { file, position: row.position, pair });
+            }
+
+            // Sort matches by similarity (higher similarity first)
+            matches.sort_by(|a, b| b.pair.similarity.partial_cmp(&a.pair.similarity).unwrap_or(Ordering::Equal));
+            matches.truncate(k);
+            Ok(matches)
+        })
+        .await
+    }
+
+    async fn keyword_search(&self, query: &str, k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        let (query, filter) = (query.to_string(), filter.clone());
+        self.with_state(move |store, state| {
+            let (Some(vectors), Some(dimensions)) = (store.map_vectors()?, state.manifest.dimensions) else {
+                return Ok(vec![]);
+            };
+
+            let ranked = state.keywords.search(&query, k, |key| {
+                let row = &state.rows[state.live[key]];
+                filter.matches(&row_file(row), row.position)
+            });
+            Ok(ranked
+                .into_iter()
+                .map(|(key, score)| {
+                    let row_index = state.live[&key];
+                    let row = &state.rows[row_index];
+                    let mut pair = EmbeddingPair::new(row.text.clone(), read_vector(&vectors, row_index, dimensions))
+                        .with_metadata(row.metadata.clone());
+                    pair.similarity = score;
+                    CorpusMatch { file: row_file(row), position: row.position, pair }
+                })
+                .collect())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_store_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("dbsearch-local-{}", std::process::id()));
+        let file = FileHash {
+            hash: "ab12".to_string(),
+            filename: "a.txt".to_string(),
+            ..Default::default()
+        };
+
+        {
+            let store = LocalStore::open(&dir).unwrap();
+            store.store_chunk(&file, 0, &EmbeddingPair::new("zero".into(), vec![1.0, 0.0])).await.unwrap();
+            store.store_chunk(&file, 1, &EmbeddingPair::new("one".into(), vec![0.0, 1.0])).await.unwrap();
+            store.store_chunk(&file, 1, &EmbeddingPair::new("one again".into(), vec![0.6, 0.8])).await.unwrap();
+            store.register_file(&file).await.unwrap();
+            store.add_to_collection("docs", &file.hash).await.unwrap();
+            store.cache_embeddings(&[("k1".to_string(), vec![0.6, 0.8])]).await.unwrap();
+            store.cache_scores(&[("s1".to_string(), 0.7)]).await.unwrap();
+        }
+
+        let store = LocalStore::open(&dir).unwrap();
+        assert!(store.is_processed("ab12").await.unwrap());
+        assert_eq!("a.txt", store.get_file("ab12").await.unwrap().unwrap().filename);
+        assert!(store.get_file("cd34").await.unwrap().is_none());
+        assert_eq!(1, store.list_files(Some("docs")).await.unwrap().len());
+        assert!(store.list_files(Some("other")).await.unwrap().is_empty());
+
+        let texts: Vec<String> = store.load_file_chunks("ab12").await.unwrap().into_iter().map(|p| p.text).collect();
+        assert_eq!(vec!["zero".to_string(), "one again".to_string()], texts);
+
+        let cached = store.get_cached_embeddings(&["k1".to_string(), "k2".to_string()]).await.unwrap();
+        assert_eq!(vec![Some(vec![0.6, 0.8]), None], cached);
+        let scores = store.get_cached_scores(&["k1".to_string(), "s1".to_string()]).await.unwrap();
+        assert_eq!(vec![None, Some(0.7)], scores);
+
+        let matches = store.search(&[0.0, 1.0], 1, &SearchFilter::default()).await.unwrap();
+        let keyword_matches = store.keyword_search("again", 5, &SearchFilter::default()).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!("one again", matches[0].pair.text);
+        // The keyword index is rebuilt on open, without the rewritten row.
+        assert_eq!(vec![1], keyword_matches.iter().map(|m| m.position).collect::<Vec<_>>());
+        assert_eq!(vec![0.6, 0.8], keyword_matches[0].pair.embedding);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_recovers_torn_writes() {
+        let dir = std::env::temp_dir().join(format!("dbsearch-local-torn-{}", std::process::id()));
+        let file = FileHash {
+            hash: "ab12".to_string(),
+            filename: "a.txt".to_string(),
+            ..Default::default()
+        };
+        {
+            let store = LocalStore::open(&dir).unwrap();
+            store.store_chunk(&file, 0, &EmbeddingPair::new("zero".into(), vec![1.0, 0.0])).await.unwrap();
+            store.store_chunk(&file, 1, &EmbeddingPair::new("one".into(), vec![0.0, 1.0])).await.unwrap();
+        }
+        // A crash left half a row, and a vector without its row.
+        let append = |name: &str, bytes: &[u8]| {
+            OpenOptions::new().append(true).open(dir.join(name)).unwrap().write_all(bytes).unwrap()
+        };
+        append(CHUNKS_FILE, b"{\"file_hash\":\"ab");
+        append(VECTORS_FILE, &[0; 12]);
+
+        {
+            let store = LocalStore::open(&dir).unwrap();
+            assert_eq!(2, store.stats().await.unwrap().chunks);
+            store.store_chunk(&file, 2, &EmbeddingPair::new("two".into(), vec![0.6, 0.8])).await.unwrap();
+        }
+        let store = LocalStore::open(&dir).unwrap();
+        let chunks = store.load_file_chunks("ab12").await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let texts: Vec<&str> = chunks.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(vec!["zero", "one", "two"], texts);
+        assert_eq!(vec![0.6, 0.8], chunks[2].embedding);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_delete() {
+        let dir = std::env::temp_dir().join(format!("dbsearch-local-delete-{}", std::process::id()));
+        let file = |hash: &str| FileHash {
+            hash: hash.to_string(),
+            filename: format!("{}.txt", hash),
+            ..Default::default()
+        };
+
+        {
+            let store = LocalStore::open(&dir).unwrap();
+            store.store_chunk(&file("ab12"), 0, &EmbeddingPair::new("a0".into(), vec![1.0, 0.0])).await.unwrap();
+            store.store_chunk(&file("ab12"), 1, &EmbeddingPair::new("a1".into(), vec![0.0, 1.0])).await.unwrap();
+            store.store_chunk(&file("cd34"), 0, &EmbeddingPair::new("c0".into(), vec![0.5, 0.5])).await.unwrap();
+            store.store_chunk(&file("cd34"), 0, &EmbeddingPair::new("c0 again".into(), vec![0.6, 0.8])).await.unwrap();
+            for hash in ["ab12", "cd34"] {
+                store.register_file(&file(hash)).await.unwrap();
+                store.add_to_collection("docs", hash).await.unwrap();
+                let record = DocumentRecord {
+                    path: format!("/docs/{}.txt", hash),
+                    hash: hash.to_string(),
+                    ..Default::default()
+                };
+                store.put_document(&record).await.unwrap();
+            }
+
+            assert_eq!(2, store.delete_file("ab12").await.unwrap());
+            assert_eq!(0, store.delete_file("ab12").await.unwrap());
+        }
+
+        let store = LocalStore::open(&dir).unwrap();
+        let stats = store.stats().await.unwrap();
+        let lines = fs::read_to_string(dir.join(CHUNKS_FILE)).unwrap().lines().count();
+        let matches = store.search(&[1.0, 0.0], 5, &SearchFilter::default()).await.unwrap();
+        let docs = store.list_files(Some("docs")).await.unwrap();
+        let documents = store.list_documents().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(StoreStats { files: 1, chunks: 1, collections: 1 }, stats);
+        // The superseded row of cd34 was dropped along with the deleted file.
+        assert_eq!(1, lines);
+        assert_eq!(vec!["c0 again".to_string()], matches.into_iter().map(|m| m.pair.text).collect::<Vec<_>>());
+        assert_eq!(vec!["cd34".to_string()], docs.into_iter().map(|f| f.hash).collect::<Vec<_>>());
+        // The registry entry of the deleted file went with it.
+        assert_eq!(vec!["/docs/cd34.txt".to_string()], documents.into_iter().map(|d| d.path).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_local_store_poisoned_lock() {
+        let dir = std::env::temp_dir().join(format!("dbsearch-local-poison-{}", std::process::id()));
+        let store = LocalStore::open(&dir).unwrap();
+        let shared = store.shared.clone();
+        let _ = std::thread::spawn(move || {
+            let _state = shared.state.lock().unwrap();
+            panic!("failed while updating the state");
+        })
+        .join();
+
+        // A half updated state is reported as an error instead of panicking every caller.
+        let result = store.stats().await;
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+}
@@ -1,177 +1,524 @@
-#![allow(unused_assignments)]
-#![allow(unused_imports)]
-#![allow(dead_code)]
-
-pub mod math;
-pub mod search;
-pub mod text;
-pub mod embed;
-pub mod redis_util;
-pub mod pdf;
-pub mod hashes;
-
-use crate::embed::*;
-use crate::pdf::*;
-use crate::hashes::*;
-
-use redis::*;
-use std::env;
-use chatgpt::prelude::*;
-use chatgpt::types::*;
-use crate::redis_util::*;
-use std::io::{Error, Result};
-use std::fs::File;
-use std::io::{self, Read};
-use clap::{App, Arg};
-//use num::ToPrimitive;
-
-use serde::{Deserialize, Serialize};
-use serde_yaml;
+use dbsearch::answer::{answer_prompt, prepare_prompt};
+use dbsearch::chat::ChatSession;
+use dbsearch::config::{load_config, DBSearchConfig};
+use dbsearch::embed::*;
+use dbsearch::hashes::compute_sha256;
+use dbsearch::index::{index_file, index_paths};
+use dbsearch::prompt::{chunk_label, format_sources};
+use dbsearch::retrieval::RetrievalArgs;
+use dbsearch::search::{SymlinkPolicy, WalkOptions};
+use dbsearch::server::{serve, ServerState};
+use dbsearch::store::{SearchFilter, VectorStore};
+use dbsearch::tokenizer::count_tokens;
+use dbsearch::{Error, Result};
+
+use clap::{Args, Parser, Subcommand};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// DB Search Tool
+#[derive(Debug, Parser)]
+#[clap(name = "dbsearch")]
+struct Cli {
+    /// Sets the config file.
+    #[clap(short, long, global = true, default_value = "config.yaml")]
+    config: String,
+    #[clap(subcommand)]
+    command: Command,
+}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DBSearchConfig {
-    pub agent_prompt: String,
-    pub query: String,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Embeds the given files and every supported file below the given directories
+    Index(IndexArgs),
+    /// Searches the indexed documents for the chunks most similar to a query
+    Query(QueryArgs),
+    /// Answers a question from the indexed documents, citing the chunks used
+    Ask(AskArgs),
+    /// Chats about the indexed documents, retrieving fresh sources for every question
+    Chat(ChatArgs),
+    /// Lists the indexed documents
+    List {
+        /// Only list documents in this collection
+        #[clap(long)]
+        collection: Option<String>,
+    },
+    /// Removes a document's chunks from the store
+    Delete {
+        /// SHA-256 hash of the document, or the path of the file it was indexed from
+        document: String,
+    },
+    /// Shows how many documents, chunks and collections are stored
+    Stats,
+    /// Hosts a local HTTP/JSON API for indexing, searching and asking
+    #[clap(alias = "host")]
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Directory the files and directories sent for indexing must lie in
+        #[clap(long, default_value = ".")]
+        root: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+struct IndexArgs {
+    /// Files and directories to index
+    #[clap(required = true)]
+    paths: Vec<PathBuf>,
+    /// Only index files matching this glob
+    #[clap(long)]
+    include: Vec<String>,
+    /// Skip files and directories matching this glob
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// Follow symbolic links instead of skipping them
+    #[clap(long)]
+    follow_symlinks: bool,
+    /// Skip files larger than this many bytes
+    #[clap(long)]
+    max_size: Option<u64>,
+    /// Adds the indexed files to a named collection
+    #[clap(long)]
+    collection: Option<String>,
+}
+
+/// Options shared by the commands that retrieve chunks for a query.
+#[derive(Debug, Args)]
+struct SearchArgs {
+    /// Query text; defaults to the query in the config file
+    #[clap(short, long)]
+    query: Option<String>,
+    /// Number of chunks to retrieve; defaults to `prompt.top_k` in the config file
+    #[clap(short = 'k', long)]
+    top_k: Option<usize>,
+    /// Only search files in this collection
+    #[clap(long)]
+    collection: Option<String>,
+    #[clap(flatten)]
+    retrieval: RetrievalArgs,
+}
+
+impl SearchArgs {
+    /// Applies the command line overrides to the configuration file.
+    fn apply(&self, config: &mut DBSearchConfig) {
+        if let Some(query) = &self.query {
+            config.query = query.clone();
+        }
+        if let Some(top_k) = self.top_k {
+            config.prompt.top_k = top_k;
+        }
+        config.retrieval = self.retrieval.apply(&config.retrieval);
+    }
+}
+
+#[derive(Debug, Args)]
+struct QueryArgs {
+    #[clap(flatten)]
+    search: SearchArgs,
+}
+
+#[derive(Debug, Args)]
+struct AskArgs {
+    #[clap(flatten)]
+    search: SearchArgs,
+    /// Only answer from these files, indexing them first if needed
+    files: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct ChatArgs {
+    /// Number of chunks retrieved per question; defaults to `prompt.top_k` in the config file
+    #[clap(short = 'k', long)]
+    top_k: Option<usize>,
+    /// Only search files in this collection
+    #[clap(long)]
+    collection: Option<String>,
+    #[clap(flatten)]
+    retrieval: RetrievalArgs,
+    /// Continues a conversation saved earlier
+    #[clap(long)]
+    restore: Option<PathBuf>,
+    /// Saves the conversation to this file when the chat ends
+    #[clap(long)]
+    save: Option<PathBuf>,
+}
+
+/// Prints what indexing reports about `path`, with embedding progress on a single,
+/// continuously rewritten line.
+fn print_event(path: &str, event: IndexEvent) {
+    match event {
+        IndexEvent::Found { files } => println!("Found {} files under {:?}", files, path),
+        IndexEvent::UnreadableDirectory { error } => {
+            println!("Skipping unreadable directory {:?}: {}", path, error)
+        }
+        IndexEvent::Skipped => println!("Skipping already processed {:?}", path),
+        IndexEvent::Reembedding { previous_model, model } => {
+            println!("Re-embedding {:?}: indexed with {} instead of {}", path, previous_model, model)
+        }
+        IndexEvent::Embedding => println!("Creating embeddings for: {:?}", path),
+        IndexEvent::Chunks { total, stored, cached, to_embed } => println!(
+            "Getting total of {} text pairs ({} stored, {} cached, {} to embed)",
+            total, stored, cached, to_embed
+        ),
+        IndexEvent::Progress(progress) => {
+            print!("\r{}: embedded {}/{} chunks", path, progress.embedded, progress.total);
+            if progress.embedded == progress.total {
+                println!();
+            }
+            let _ = io::Write::flush(&mut io::stdout());
+        }
+        IndexEvent::PageSkipped { page, error } => {
+            println!("Unable to extract page {} of {:?}: {}", page, path, error)
+        }
+        IndexEvent::BatchFailed { error } => println!("\nUnable to embed a batch of {:?}: {}", path, error),
+        IndexEvent::Failed { error } => println!("Unable to index {:?}: {}", path, error),
+        IndexEvent::Removed => println!("Removing deleted {:?}", path),
+    }
 }
 
-/// Load the configuration file.
-pub fn load_config(filename: String) -> std::result::Result<DBSearchConfig, std::io::Error> {
-    let yaml_file_r = File::open(filename);
-    let mut yaml_file = match yaml_file_r {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Error opening YAML file: {:?}", e);
-            return Err(e);
+/// Runs the `index` subcommand over files and directory trees.
+async fn run_index(args: &IndexArgs, config: &DBSearchConfig) -> Result<()> {
+    let symlinks = if args.follow_symlinks {
+        SymlinkPolicy::Follow
+    } else {
+        SymlinkPolicy::Skip
+    };
+    let options = WalkOptions::from_globs(&args.include, &args.exclude, symlinks, args.max_size)
+        .map_err(|e| Error::Config(format!("Invalid glob: {}", e)))?;
+
+    let embedder = config.embedder()?;
+    let chunker = config.chunker()?;
+    let store = config.open_store().await?;
+    let indexing = index_paths(
+        store.as_ref(),
+        &embedder,
+        &chunker,
+        &args.paths,
+        &options,
+        args.collection.as_deref(),
+        config.concurrency,
+        &print_event,
+    );
+    // Dropping the indexing future on ctrl-c cancels any in-flight embedding requests.
+    let summary = tokio::select! {
+        summary = indexing => summary?,
+        _ = tokio::signal::ctrl_c() => {
+            println!("\nIndexing interrupted; files not fully embedded will be redone on the next run.");
+            return Ok(());
         }
     };
+    println!(
+        "Indexed {} new and {} changed files, resumed {}, removed {} deleted ({} already processed, {} unsupported, {} failed)",
+        summary.embedded,
+        summary.changed,
+        summary.resumed,
+        summary.removed,
+        summary.skipped,
+        summary.unsupported,
+        summary.failed
+    );
+    println!(
+        "Embedding cache: {} hits, {} misses",
+        summary.cache_hits, summary.cache_misses
+    );
+    Ok(())
+}
 
-    let mut yaml_content = String::new();
-    yaml_file.read_to_string(&mut yaml_content).unwrap();
+/// Runs the `query` subcommand across the whole corpus or a single collection.
+async fn run_query(args: &QueryArgs, config: &DBSearchConfig) -> Result<()> {
+    let embedder = config.embedder()?;
+    let store = config.open_store().await?;
+    let start_vecsearch = Instant::now();
+    let corpus_matches = search_corpus(
+        store.as_ref(),
+        &embedder,
+        &config.query,
+        config.prompt.top_k,
+        args.search.collection.as_deref(),
+        &config.retrieval,
+    )
+    .await?;
+    println!("Embedding vector search({:?})", start_vecsearch.elapsed());
 
-    //Load the YAML file.
-    let config: DBSearchConfig = serde_yaml::from_str(
-        &yaml_content.as_str().to_owned()
-    ).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Error parsing YAML: {}", e)))?;
-    
-    Ok(config)
+    for (rank, corpus_match) in corpus_matches.iter().enumerate() {
+        println!(
+            "{}. [{:.4}] {}\n{}\n",
+            rank + 1,
+            corpus_match.pair.similarity,
+            chunk_label(corpus_match),
+            corpus_match.pair.text
+        );
+    }
+    Ok(())
 }
 
-fn concatenate_strings_for_query(strings: Vec<&str>) -> String {
-    let mut result = String::new();
-    for s in strings {
-        result.push_str(format!("{:?}. ", s).as_str());
+/// Returns the hashes of the documents in a collection, which must not be empty.
+async fn collection_hashes(store: &dyn VectorStore, collection: &str) -> Result<Vec<String>> {
+    let hashes: Vec<String> = store
+        .list_files(Some(collection))
+        .await?
+        .into_iter()
+        .map(|file| file.hash)
+        .collect();
+    if hashes.is_empty() {
+        return Err(Error::NotFound(format!("No indexed documents in collection {}", collection)));
     }
-    result
+    Ok(hashes)
 }
 
-#[tokio::main]
-async fn main() -> std::result::Result<(), std::io::Error> {
-    // Specify the name of the environment variable you want to retrieve
-    let var_name = "OPENAI_API_KEY";
-    let args: Vec<String> = env::args().collect();
+/// Runs the `ask` subcommand, answering the query from the corpus, a collection or the given files.
+async fn run_ask(args: &AskArgs, config: &DBSearchConfig) -> Result<()> {
+    let embedder = config.embedder()?;
+    let chunker = config.chunker()?;
+    let store = config.open_store().await?;
+
+    let mut filter = SearchFilter::default();
+    for file_to_process in &args.files {
+        if !Path::new(file_to_process).is_file() {
+            return Err(Error::NotFound(file_to_process.to_string()));
+        }
+        if is_file_processed(store.as_ref(), file_to_process).await? {
+            println!("Using stored embeddings for {:?}", file_to_process);
+        } else {
+            let limiter = tokio::sync::Semaphore::new(config.concurrency.max(1));
+            let report = |event: IndexEvent| print_event(file_to_process, event);
+            tokio::select! {
+                outcome = index_file(store.as_ref(), &embedder, &chunker, file_to_process, None, &limiter, &report) => outcome?,
+                _ = tokio::signal::ctrl_c() => {
+                    println!("\nEmbedding interrupted.");
+                    return Ok(());
+                }
+            };
+        }
+        filter.file_hashes.push(compute_sha256(file_to_process)?);
+    }
+    if let Some(collection) = &args.search.collection {
+        let hashes: Vec<String> = collection_hashes(store.as_ref(), collection)
+            .await?
+            .into_iter()
+            .filter(|hash| args.files.is_empty() || filter.file_hashes.contains(hash))
+            .collect();
+        if hashes.is_empty() {
+            return Err(Error::NotFound(format!("None of the files are in collection {}", collection)));
+        }
+        filter.file_hashes = hashes;
+    }
+
+    let start_vecsearch = Instant::now();
+    let prompt = prepare_prompt(
+        store.as_ref(),
+        &embedder,
+        &config.agent_prompt,
+        &config.query,
+        &config.prompt,
+        &config.retrieval,
+        chunker.tokenizer(),
+        &filter,
+    )
+    .await?;
+    println!("Embedding vector search({:?})", start_vecsearch.elapsed());
+
+    let prompt_tokens: usize = prompt
+        .messages
+        .iter()
+        .map(|message| count_tokens(chunker.tokenizer(), &message.content))
+        .sum();
+    println!("Sending {} sources ({} prompt tokens)", prompt.sources.len(), prompt_tokens);
+
+    let start = Instant::now();
+    let answer = answer_prompt(&embedder, prompt).await?;
+    println!("Response({:?}): {}", start.elapsed(), answer.with_sources());
+    Ok(())
+}
+
+/// Runs the `chat` subcommand: a read-eval-print loop over standard input.
+async fn run_chat(args: &ChatArgs, config: &DBSearchConfig) -> Result<()> {
+    let embedder = config.embedder()?;
+    let chunker = config.chunker()?;
+    let store = config.open_store().await?;
 
-    if args.len() <= 1 {
-        return Ok(());
+    let mut filter = SearchFilter::default();
+    if let Some(collection) = &args.collection {
+        filter.file_hashes = collection_hashes(store.as_ref(), collection).await?;
     }
+    let mut session = match &args.restore {
+        Some(path) => ChatSession::restore(&embedder, path, filter).await?,
+        None => ChatSession::new(&embedder, &config.agent_prompt, filter),
+    };
+    session.retrieval = args.retrieval.apply(&config.retrieval);
 
-    let matches = App::new("dbsearch")
-        .about("DB Search Tool")
-        .arg(Arg::with_name("filename")
-            .index(1)
-            .required(true)
-            .help("Sets the input file"))
-        .arg(Arg::with_name("config")
-            .short('c')
-            .required(false)
-            .default_value("config.yaml")
-            .help("Sets the config file."))
-        .arg(Arg::with_name("verbose")
-            .short('v')
-            .required(false)
-            .help("Sets the level of verbosity"))
-        .arg(Arg::with_name("host")
-            .short('h')
-            .required(false)
-            .help("Host a manager instance"))
-        .get_matches();
-
-    let yaml_filename = matches.value_of("config").unwrap();
-
-    // Get the first argument (index 0 is the program name)
-    let file_to_process = matches.value_of("filename").unwrap();
-    //let summary_query = &args[2];
-
-    let config = load_config(yaml_filename.to_string()).unwrap();
-    let agent_prompt = config.agent_prompt.clone();
-    let query = config.query.clone();
-
-    // Get the value of the environment variable
-    match env::var(var_name) {
-        Ok(val) => {
-            println!("The value of {} is: {}", var_name, val);
-
-            // Creating a new ChatGPT client.
-            // Note that it requires an API key, and uses
-            // tokens from your OpenAI API account balance.
-            let client = ChatGPT::new(val).unwrap();
-
-            let mut emb_pairs: Vec<EmbeddingPair> = vec![];
-            
-            if is_file_processed(file_to_process).await {
-                emb_pairs = get_embedding_vectors(file_to_process).await;
-                println!("Using {:?} stored embeddings for {:?}", emb_pairs.len(), file_to_process);
-            } else {
-                emb_pairs = create_embedding_list(file_to_process).await;
-                println!("Creating embeddings for: {:?}", file_to_process);
+    println!("Ask about the indexed documents. Type /save <file> to save the conversation, /exit to leave.");
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        let _ = io::Write::flush(&mut io::stdout());
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "/exit" || line == "/quit" {
+            break;
+        }
+        if let Some(path) = line.strip_prefix("/save") {
+            match session.save(Path::new(path.trim())).await {
+                Ok(()) => println!("Saved conversation to {}", path.trim()),
+                Err(e) => eprintln!("Error: {}", e),
             }
+            continue;
+        }
 
-            let start_vecsearch = Instant::now();
-            let similar_entries = search_for_similar_entries(
-                query.clone(),
-                3,
-                &mut emb_pairs
-            ).await;
-            
-            let duration_vecsearch = start_vecsearch.elapsed();
-            println!("Embedding vector search({:?})", duration_vecsearch);
-
-            let text1 = &similar_entries[0].text;
-            let text2 = &similar_entries[1].text;
-            let text3 = &similar_entries[2].text;
-            concatenate_strings_for_query(vec![text1, text2, text3]);
-
-            //println!("Response: {:?}", response.embeddings());
-            let history_array = vec![
-                ChatMessage {
-                    role: Role::System,
-                    content: format!("{}\n\n{:?}", agent_prompt.clone(), text1),
-                },
-                ChatMessage {
-                    role: Role::User,
-                    content: format!("{}", &query.clone()),
-                },
-            ];
-
-            let start = Instant::now();
-            let response2 = client
-                .send_history(&history_array)
-                .await
-                .unwrap();
-            let duration = start.elapsed();
-            
-            println!("Response({:?}): {}", duration, response2.message().content);            
-        } 
-        Err(_) => println!("{} is not defined in the environment.", var_name),
+        let mut print_delta = |delta: &str| {
+            print!("{}", delta);
+            let _ = io::Write::flush(&mut io::stdout());
+        };
+        match session
+            .ask(store.as_ref(), &embedder, line, &config.prompt, chunker.tokenizer(), &mut print_delta)
+            .await
+        {
+            Ok(answer) => println!("\n\n{}", format_sources(&answer.prompt, &answer.text)),
+            // A failed turn leaves the conversation as it was, so the chat can go on.
+            Err(e) => eprintln!("Error: {}", e),
+        }
     }
+
+    if let Some(path) = &args.save {
+        session.save(path).await?;
+        println!("Saved conversation to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Runs the `list` subcommand.
+async fn run_list(collection: Option<&str>, config: &DBSearchConfig) -> Result<()> {
+    let store = config.open_store().await?;
+    let files = store.list_files(collection).await?;
+    for file in &files {
+        println!("{}  {}  {}", file.hash, file.filename, file.model);
+    }
+    println!("{} documents", files.len());
+    Ok(())
+}
+
+/// Runs the `delete` subcommand, accepting a document hash or the path of an indexed file.
+async fn run_delete(document: &str, config: &DBSearchConfig) -> Result<()> {
+    let store = config.open_store().await?;
+    let file_sha256_hash = if Path::new(document).is_file() {
+        compute_sha256(document)?
+    } else {
+        document.to_string()
+    };
+    let registered = store.get_file(&file_sha256_hash).await?;
+    let removed = store.delete_file(&file_sha256_hash).await?;
+    if registered.is_none() && removed == 0 {
+        return Err(Error::NotFound(format!("No indexed document {}", document)));
+    }
+    println!("Deleted {} chunks of {}", removed, file_sha256_hash);
+    Ok(())
+}
+
+/// Runs the `stats` subcommand.
+async fn run_stats(config: &DBSearchConfig) -> Result<()> {
+    let store = config.open_store().await?;
+    let stats = store.stats().await?;
+    println!("Documents:   {}", stats.files);
+    println!("Chunks:      {}", stats.chunks);
+    println!("Collections: {}", stats.collections);
     Ok(())
 }
 
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let mut config = load_config(cli.config.clone())?;
+    match &cli.command {
+        Command::Index(args) => run_index(args, &config).await,
+        Command::Query(args) => {
+            args.search.apply(&mut config);
+            run_query(args, &config).await
+        }
+        Command::Ask(args) => {
+            args.search.apply(&mut config);
+            run_ask(args, &config).await
+        }
+        Command::Chat(args) => {
+            if let Some(top_k) = args.top_k {
+                config.prompt.top_k = top_k;
+            }
+            run_chat(args, &config).await
+        }
+        Command::List { collection } => run_list(collection.as_deref(), &config).await,
+        Command::Delete { document } => run_delete(document, &config).await,
+        Command::Stats => run_stats(&config).await,
+        Command::Serve { addr, root } => serve(ServerState::from_config(&config, root.clone()).await?, *addr).await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::CommandFactory;
+    use dbsearch::retrieval::RetrievalMode;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["dbsearch", "-c", "other.yml", "ask", "--query", "Why?", "-k", "7", "a.pdf"]);
+        assert_eq!("other.yml", cli.config);
+        let Command::Ask(args) = cli.command else {
+            panic!("expected the ask command");
+        };
+        assert_eq!(vec!["a.pdf".to_string()], args.files);
+
+        let mut config: DBSearchConfig = serde_yaml::from_str("agent_prompt: Be brief.\nquery: What?\n").unwrap();
+        args.search.apply(&mut config);
+        assert_eq!("Why?", config.query);
+        assert_eq!(7, config.prompt.top_k);
+
+        // Chat shares the retrieval flags with ask and query.
+        let cli = Cli::parse_from(["dbsearch", "chat", "--mode", "hybrid", "--rerank", "--mmr-lambda", "0.7"]);
+        let Command::Chat(args) = cli.command else {
+            panic!("expected the chat command");
+        };
+        let retrieval = args.retrieval.apply(&config.retrieval);
+        assert_eq!((RetrievalMode::Hybrid, Some(0.7)), (retrieval.mode, retrieval.mmr_lambda));
+        assert!(retrieval.rerank.is_some());
+        let cli = Cli::parse_from(["dbsearch", "query", "--rerank=false"]);
+        let Command::Query(args) = cli.command else {
+            panic!("expected the query command");
+        };
+        assert!(args.search.retrieval.apply(&retrieval).rerank.is_none());
+
+        let cli = Cli::parse_from(["dbsearch", "index", "docs", "notes.md", "--include", "*.pdf", "--max-size", "10"]);
+        let Command::Index(args) = cli.command else {
+            panic!("expected the index command");
+        };
+        assert_eq!(vec![PathBuf::from("docs"), PathBuf::from("notes.md")], args.paths);
+        assert_eq!(Some(10), args.max_size);
+
+        // `-h` is help, not a host name.
+        assert!(Cli::try_parse_from(["dbsearch", "-h", "localhost"]).is_err());
+    }
 
     /*
+    use chatgpt::types::EmbeddingCompletionResponse;
+
     #[test]
     fn test_serdes_1() {
         let data = r#"{\n  \"object\": \"list\",\n  \"data\": [\n    {\n      \"object\": \"embedding\",\n      \"index\": 0,\n      \"embedding\": [\n        -0.0502362,\n        0.036481638,\n    ]\n    }\n  ],\n  \"model\": \"text-embedding-3-small\",\n  \"usage\": {\n    \"prompt_tokens\": 9,\n    \"total_tokens\": 9\n  }\n}\n"#;
@@ -1,4 +1,152 @@
-pub fn extract_pdf_text(filename: &str) -> String {
-    let bytes = std::fs::read(filename).unwrap();
-    pdf_extract::extract_text_from_mem(&bytes).unwrap()
+use crate::error::{Error, Result};
+
+use pdf_extract::{output_doc_page, Document, PlainTextOutput};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Text extracted from one page of a PDF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// 1-based page number.
+    pub number: u32,
+    pub text: String,
+}
+
+/// A page whose text could not be extracted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageError {
+    pub number: u32,
+    pub message: String,
+}
+
+/// Pages of a PDF, with the pages that failed to extract reported separately.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PdfPages {
+    pub pages: Vec<Page>,
+    pub errors: Vec<PageError>,
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "extraction panicked".to_string()),
+    }
+}
+
+fn extract_page(doc: &Document, number: u32) -> std::result::Result<String, String> {
+    // pdf-extract panics on some malformed content streams; keep that from ending the run.
+    let extracted = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut text = String::new();
+        let mut output = PlainTextOutput::new(&mut text);
+        output_doc_page(doc, &mut output, number).map(|_| text)
+    }));
+    match extracted {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(payload) => Err(panic_message(payload.as_ref())),
+    }
+}
+
+/// Extracts the text of every page of an in-memory PDF.
+///
+/// Only a document that cannot be parsed at all is an error; failing pages are listed in
+/// [`PdfPages::errors`] and the remaining pages are still returned.
+pub fn extract_pdf_pages_from_mem(bytes: &[u8]) -> Result<PdfPages> {
+    let invalid = |e: pdf_extract::Error| Error::Pdf(format!("Invalid PDF: {}", e));
+    let mut doc = Document::load_mem(bytes).map_err(invalid)?;
+    if doc.is_encrypted() {
+        // Documents protected by an empty user password can still be read.
+        doc.decrypt("").map_err(invalid)?;
+    }
+
+    let mut result = PdfPages::default();
+    for number in doc.get_pages().into_keys() {
+        match extract_page(&doc, number) {
+            Ok(text) => result.pages.push(Page { number, text }),
+            Err(message) => result.errors.push(PageError { number, message }),
+        }
+    }
+    Ok(result)
+}
+
+/// Extracts the text of every page of the PDF at `filename`.
+pub fn extract_pdf_pages(filename: &str) -> Result<PdfPages> {
+    extract_pdf_pages_from_mem(&std::fs::read(filename)?)
+}
+
+/// Extracts the text of a whole PDF, skipping pages that fail to extract.
+pub fn extract_pdf_text(filename: &str) -> Result<String> {
+    let pages = extract_pdf_pages(filename)?.pages;
+    Ok(pages.into_iter().map(|page| page.text).collect::<Vec<_>>().join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_extract::{dictionary, Object, Stream};
+
+    /// Builds a PDF whose pages show the given texts; `None` makes a page with a broken content stream.
+    fn build_pdf(pages: &[Option<&str>]) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        let mut kids: Vec<Object> = Vec::new();
+        for text in pages {
+            let content = match text {
+                Some(text) => format!("BT /F1 24 Tf 100 600 Td ({}) Tj ET", text),
+                None => "BT /F9 24 Tf 100 600 Td (broken) Tj ET".to_string(),
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            });
+            kids.push(page_id.into());
+        }
+        let count = kids.len() as i64;
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => count,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_extract_pages() {
+        let pdf = build_pdf(&[Some("First page"), None, Some("Third page")]);
+        let extracted = extract_pdf_pages_from_mem(&pdf).unwrap();
+
+        let numbers: Vec<u32> = extracted.pages.iter().map(|page| page.number).collect();
+        assert_eq!(vec![1, 3], numbers);
+        assert!(extracted.pages[0].text.contains("First page"));
+        assert!(extracted.pages[1].text.contains("Third page"));
+        assert_eq!(2, extracted.errors[0].number);
+
+        assert!(matches!(extract_pdf_pages_from_mem(b"not a pdf"), Err(Error::Pdf(_))));
+    }
 }
@@ -0,0 +1,204 @@
+use crate::embed::CorpusMatch;
+use crate::tokenizer::{count_tokens, Tokenizer};
+
+use chatgpt::types::{ChatMessage, Role};
+use serde::{Deserialize, Serialize};
+
+/// The `prompt` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptConfig {
+    /// Number of chunks retrieved for each question.
+    pub top_k: usize,
+    /// Maximum number of tokens spent on the system prompt, the sources and the question.
+    pub max_context_tokens: usize,
+    /// Appended after the sources to tell the model how to cite them.
+    pub citation_instruction: String,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        Self {
+            top_k: 5,
+            max_context_tokens: 3000,
+            citation_instruction: "Answer using only the sources above. Cite every source you use by its label, \
+                e.g. [paper.pdf#3]. If the sources do not contain the answer, say so."
+                .to_string(),
+        }
+    }
+}
+
+/// Returns the label a chunk is cited by: its source file and chunk id.
+pub fn chunk_label(chunk: &CorpusMatch) -> String {
+    format!("[{}#{}]", chunk.file.filename, chunk.position)
+}
+
+/// Chat messages asking a question about retrieved chunks, and the chunks they include.
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub messages: Vec<ChatMessage>,
+    /// Chunks that fit into the token budget, in ranking order.
+    pub sources: Vec<CorpusMatch>,
+}
+
+impl Prompt {
+    /// Returns the sources whose label appears in `answer`.
+    pub fn cited_sources(&self, answer: &str) -> Vec<&CorpusMatch> {
+        self.sources
+            .iter()
+            .filter(|source| answer.contains(&chunk_label(source)))
+            .collect()
+    }
+}
+
+fn source_block(chunk: &CorpusMatch) -> String {
+    format!("{}\n{}\n\n", chunk_label(chunk), chunk.pair.text.trim())
+}
+
+/// Adds the ranked `chunks` to a sources block in ranking order, until the next one would bring
+/// `used_tokens` over `max_context_tokens`.
+fn pack_sources(
+    chunks: &[CorpusMatch],
+    config: &PromptConfig,
+    tokenizer: Option<&Tokenizer>,
+    mut used_tokens: usize,
+) -> (String, Vec<CorpusMatch>) {
+    let mut sources: Vec<CorpusMatch> = Vec::new();
+    let mut context = String::new();
+    for chunk in chunks.iter().take(config.top_k) {
+        let block = source_block(chunk);
+        let block_tokens = count_tokens(tokenizer, &block);
+        if used_tokens + block_tokens > config.max_context_tokens {
+            break;
+        }
+        used_tokens += block_tokens;
+        context.push_str(&block);
+        sources.push(chunk.clone());
+    }
+    (context, sources)
+}
+
+/// Builds the messages answering `query` from the ranked `chunks`.
+///
+/// Chunks are added in ranking order until the next one would exceed `max_context_tokens`,
+/// counted with `tokenizer` or estimated without one.
+pub fn build_prompt(
+    agent_prompt: &str,
+    query: &str,
+    chunks: &[CorpusMatch],
+    config: &PromptConfig,
+    tokenizer: Option<&Tokenizer>,
+) -> Prompt {
+    let estimate_tokens = |text: &str| count_tokens(tokenizer, text);
+    let used_tokens = estimate_tokens(agent_prompt)
+        + estimate_tokens(&config.citation_instruction)
+        + estimate_tokens(query);
+    let (context, sources) = pack_sources(chunks, config, tokenizer, used_tokens);
+
+    let messages = vec![
+        ChatMessage {
+            role: Role::System,
+            content: format!("{}\n\nSources:\n\n{}{}", agent_prompt, context, config.citation_instruction),
+        },
+        ChatMessage {
+            role: Role::User,
+            content: query.to_string(),
+        },
+    ];
+    Prompt { messages, sources }
+}
+
+/// Builds the user message of one chat turn: the sources retrieved for `query`, then the question.
+///
+/// `history_tokens` counts the conversation so far, which shares the `max_context_tokens` budget.
+pub fn build_chat_turn(
+    query: &str,
+    chunks: &[CorpusMatch],
+    config: &PromptConfig,
+    tokenizer: Option<&Tokenizer>,
+    history_tokens: usize,
+) -> Prompt {
+    let used_tokens = history_tokens
+        + count_tokens(tokenizer, &config.citation_instruction)
+        + count_tokens(tokenizer, query);
+    let (context, sources) = pack_sources(chunks, config, tokenizer, used_tokens);
+
+    let messages = vec![ChatMessage {
+        role: Role::User,
+        content: format!("Sources:\n\n{}{}\n\nQuestion: {}", context, config.citation_instruction, query),
+    }];
+    Prompt { messages, sources }
+}
+
+/// Formats an answer followed by the sources it cites, or every included source if it cites none.
+pub fn format_answer(prompt: &Prompt, answer: &str) -> String {
+    format!("{}\n\n{}", answer.trim(), format_sources(prompt, answer))
+}
+
+/// Lists the sources `answer` cites, or every included source if it cites none.
+pub fn format_sources(prompt: &Prompt, answer: &str) -> String {
+    let mut cited = prompt.cited_sources(answer);
+    if cited.is_empty() {
+        cited = prompt.sources.iter().collect();
+    }
+
+    let mut output = "Sources:\n".to_string();
+    for source in cited {
+        let page = match source.pair.metadata.page {
+            Some(page) => format!("page {}, ", page),
+            None => String::new(),
+        };
+        output.push_str(&format!("  {} ({}similarity {:.4})\n", chunk_label(source), page, source.pair.similarity));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::{EmbeddingPair, FileHash};
+
+    fn chunk(filename: &str, position: usize, text: &str) -> CorpusMatch {
+        CorpusMatch {
+            file: FileHash {
+                hash: "ab12".to_string(),
+                filename: filename.to_string(),
+                ..Default::default()
+            },
+            position,
+            pair: EmbeddingPair::new(text.to_string(), vec![]),
+        }
+    }
+
+    #[test]
+    fn test_build_prompt() {
+        let chunks = vec![
+            chunk("a.pdf", 3, "first chunk"),
+            chunk("b.txt", 0, "second chunk"),
+            chunk("c.md", 7, &"long ".repeat(400)),
+        ];
+        let config = PromptConfig {
+            top_k: 3,
+            max_context_tokens: 200,
+            ..Default::default()
+        };
+        let prompt = build_prompt("You answer questions.", "What is first?", &chunks, &config, None);
+
+        // The third chunk does not fit into the budget.
+        assert_eq!(2, prompt.sources.len());
+        assert!(prompt.messages[0].content.contains("[a.pdf#3]\nfirst chunk"));
+        assert!(prompt.messages[0].content.contains("[b.txt#0]\nsecond chunk"));
+        assert!(!prompt.messages[0].content.contains("[c.md#7]"));
+        assert_eq!("What is first?", prompt.messages[1].content);
+
+        let answer = "It is the first chunk [a.pdf#3].";
+        assert_eq!(1, prompt.cited_sources(answer).len());
+        assert!(format_answer(&prompt, answer).ends_with("Sources:\n  [a.pdf#3] (similarity 0.0000)\n"));
+
+        // A chat turn carries its sources with the question, within what the history leaves.
+        let turn = build_chat_turn("What is first?", &chunks, &config, None, 190);
+        assert_eq!(1, turn.messages.len());
+        assert!(turn.messages[0].content.ends_with("Question: What is first?"));
+        assert!(turn.sources.len() < prompt.sources.len());
+    }
+}
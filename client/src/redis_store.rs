@@ -0,0 +1,276 @@
+use crate::embed::{CorpusMatch, EmbeddingPair, FileHash};
+use crate::store::*;
+use crate::vector_index::*;
+
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::*;
+use tokio::sync::OnceCell;
+
+/// Redis hash mapping each indexed file's SHA-256 hash to its serialized [`FileHash`].
+pub const FILES_KEY: &str = "dbsearch:files";
+
+/// Redis hash mapping each document's canonical path to its serialized [`DocumentRecord`].
+pub const DOCUMENTS_KEY: &str = "dbsearch:documents";
+
+/// Redis hash mapping embedding cache keys to little-endian f32 vectors.
+pub const EMBEDDING_CACHE_KEY: &str = "dbsearch:embedding-cache";
+
+/// Redis hash mapping rerank cache keys to relevance scores.
+pub const RERANK_CACHE_KEY: &str = "dbsearch:rerank-cache";
+
+/// Redis set of the names of every collection.
+pub const COLLECTIONS_KEY: &str = "dbsearch:collections";
+
+/// Key prefix of the Redis sets holding the file hashes of each named collection.
+pub const COLLECTION_PREFIX: &str = "dbsearch:collection:";
+
+/// Returns the Redis set holding the file hashes belonging to a named collection.
+pub fn collection_key(collection: &str) -> String {
+    format!("{}{}", COLLECTION_PREFIX, collection)
+}
+
+/// Vector store backed by Redis Stack and its RediSearch vector index.
+///
+/// Every operation works on a clone of one [`ConnectionManager`], so concurrent
+/// tasks share a single multiplexed connection.
+pub struct RedisStore {
+    config: VectorIndexConfig,
+    connection: ConnectionManager,
+    index_ready: OnceCell<()>,
+}
+
+impl RedisStore {
+    /// Connects using the `REDIS_HOSTNAME`, `REDIS_PASSWORD` and `IS_TLS` environment variables.
+    pub async fn connect(config: VectorIndexConfig) -> StoreResult<RedisStore> {
+        let mut connection = crate::redis_util::connect_to_redis().await?;
+        // The index can only be created once the embedding width is known, on the first chunk.
+        let index_ready = OnceCell::new();
+        if index_exists(&mut connection).await? {
+            let _ = index_ready.set(());
+        }
+        Ok(RedisStore {
+            config,
+            connection,
+            index_ready,
+        })
+    }
+
+    /// Whether the vector index exists, possibly created by another process since connecting.
+    /// Without it, nothing has been stored and every query finds nothing.
+    async fn has_index(&self) -> StoreResult<bool> {
+        if self.index_ready.initialized() {
+            return Ok(true);
+        }
+        if index_exists(&mut self.connection()).await? {
+            let _ = self.index_ready.set(());
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn connection(&self) -> ConnectionManager {
+        self.connection.clone()
+    }
+}
+
+#[async_trait]
+impl VectorStore for RedisStore {
+    async fn store_chunk(&self, file: &FileHash, position: usize, pair: &EmbeddingPair) -> StoreResult<()> {
+        let mut connection = self.connection();
+        self.index_ready
+            .get_or_try_init(|| async {
+                ensure_index(&mut self.connection(), &self.config, pair.embedding.len()).await
+            })
+            .await?;
+        //Store every chunk as its own hash so the RediSearch vector index picks it up.
+        store_chunk(&mut connection, file, position, pair).await?;
+        Ok(())
+    }
+
+    async fn register_file(&self, file: &FileHash) -> StoreResult<()> {
+        let serialized_data: String = serde_json::to_string(file)?;
+        self.connection()
+            .hset::<_, _, _, ()>(FILES_KEY, &file.hash, serialized_data)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_processed(&self, file_sha256_hash: &str) -> StoreResult<bool> {
+        // A file is only registered once all of its chunks have been stored.
+        Ok(self.connection().hexists(FILES_KEY, file_sha256_hash).await?)
+    }
+
+    async fn get_file(&self, file_sha256_hash: &str) -> StoreResult<Option<FileHash>> {
+        let value: Option<String> = self.connection().hget(FILES_KEY, file_sha256_hash).await?;
+        match value {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn load_file_chunks(&self, file_sha256_hash: &str) -> StoreResult<Vec<EmbeddingPair>> {
+        if !self.has_index().await? {
+            return Ok(vec![]);
+        }
+        Ok(load_file_chunks(&mut self.connection(), file_sha256_hash).await?)
+    }
+
+    async fn list_files(&self, collection: Option<&str>) -> StoreResult<Vec<FileHash>> {
+        let mut connection = self.connection();
+        let values: Vec<String> = match collection {
+            Some(name) => {
+                let hashes: Vec<String> = connection.smembers(collection_key(name)).await?;
+                if hashes.is_empty() {
+                    return Ok(vec![]);
+                }
+                let values: Vec<Option<String>> = connection.hget(FILES_KEY, hashes).await?;
+                values.into_iter().flatten().collect()
+            }
+            None => connection.hvals(FILES_KEY).await?,
+        };
+        let mut files = values
+            .iter()
+            .map(|value| serde_json::from_str(value))
+            .collect::<std::result::Result<Vec<FileHash>, _>>()?;
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(files)
+    }
+
+    async fn add_to_collection(&self, collection: &str, file_sha256_hash: &str) -> StoreResult<()> {
+        let mut connection = self.connection();
+        connection.sadd::<_, _, ()>(COLLECTIONS_KEY, collection).await?;
+        connection
+            .sadd::<_, _, ()>(collection_key(collection), file_sha256_hash)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_file(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        let mut connection = self.connection();
+        // Unregister first so a partially deleted file is never reported as processed.
+        connection.hdel::<_, _, ()>(FILES_KEY, file_sha256_hash).await?;
+        let collections: Vec<String> = connection.smembers(COLLECTIONS_KEY).await?;
+        for name in collections {
+            connection.srem::<_, _, ()>(collection_key(&name), file_sha256_hash).await?;
+            // Redis drops empty sets, so the name goes too.
+            if !connection.exists::<_, bool>(collection_key(&name)).await? {
+                connection.srem::<_, _, ()>(COLLECTIONS_KEY, &name).await?;
+            }
+        }
+        for record in self.list_documents().await? {
+            if record.hash == file_sha256_hash {
+                connection.hdel::<_, _, ()>(DOCUMENTS_KEY, &record.path).await?;
+            }
+        }
+        self.delete_chunks(file_sha256_hash).await
+    }
+
+    async fn delete_chunks(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        if !self.has_index().await? {
+            return Ok(0);
+        }
+        Ok(delete_file_chunks(&mut self.connection(), file_sha256_hash).await?)
+    }
+
+    async fn get_document(&self, path: &str) -> StoreResult<Option<DocumentRecord>> {
+        let value: Option<String> = self.connection().hget(DOCUMENTS_KEY, path).await?;
+        match value {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_document(&self, record: &DocumentRecord) -> StoreResult<()> {
+        let serialized_data: String = serde_json::to_string(record)?;
+        self.connection()
+            .hset::<_, _, _, ()>(DOCUMENTS_KEY, &record.path, serialized_data)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_document(&self, path: &str) -> StoreResult<()> {
+        self.connection().hdel::<_, _, ()>(DOCUMENTS_KEY, path).await?;
+        Ok(())
+    }
+
+    async fn list_documents(&self) -> StoreResult<Vec<DocumentRecord>> {
+        let values: Vec<String> = self.connection().hvals(DOCUMENTS_KEY).await?;
+        let mut records = values
+            .iter()
+            .map(|value| serde_json::from_str(value))
+            .collect::<std::result::Result<Vec<DocumentRecord>, _>>()?;
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(records)
+    }
+
+    async fn get_cached_embeddings(&self, keys: &[String]) -> StoreResult<Vec<Option<Vec<f32>>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let values: Vec<Option<Vec<u8>>> = redis::cmd("HMGET")
+            .arg(EMBEDDING_CACHE_KEY)
+            .arg(keys)
+            .query_async(&mut self.connection())
+            .await?;
+        Ok(values.into_iter().map(|value| value.map(|bytes| bytes_to_embedding(&bytes))).collect())
+    }
+
+    async fn cache_embeddings(&self, entries: &[(String, Vec<f32>)]) -> StoreResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let fields: Vec<(&str, Vec<u8>)> = entries
+            .iter()
+            .map(|(key, embedding)| (key.as_str(), embedding_to_bytes(embedding)))
+            .collect();
+        self.connection()
+            .hset_multiple::<_, _, _, ()>(EMBEDDING_CACHE_KEY, &fields)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_cached_scores(&self, keys: &[String]) -> StoreResult<Vec<Option<f32>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(redis::cmd("HMGET")
+            .arg(RERANK_CACHE_KEY)
+            .arg(keys)
+            .query_async(&mut self.connection())
+            .await?)
+    }
+
+    async fn cache_scores(&self, entries: &[(String, f32)]) -> StoreResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.connection()
+            .hset_multiple::<_, _, _, ()>(RERANK_CACHE_KEY, entries)
+            .await?;
+        Ok(())
+    }
+
+    async fn stats(&self) -> StoreResult<StoreStats> {
+        let mut connection = self.connection();
+        Ok(StoreStats {
+            files: connection.hlen(FILES_KEY).await?,
+            chunks: index_document_count(&mut connection).await?,
+            collections: connection.scard(COLLECTIONS_KEY).await?,
+        })
+    }
+
+    async fn search(&self, embedding: &[f32], k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        if !self.has_index().await? {
+            return Ok(vec![]);
+        }
+        Ok(knn_search(&mut self.connection(), &self.config, embedding, k, filter).await?)
+    }
+
+    async fn keyword_search(&self, query: &str, k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        if !self.has_index().await? {
+            return Ok(vec![]);
+        }
+        Ok(keyword_search(&mut self.connection(), query, k, filter).await?)
+    }
+}
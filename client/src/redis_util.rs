@@ -1,10 +1,12 @@
-use redis::*;
+use crate::error::{Error, Result};
+
+use redis::aio::ConnectionManager;
 use std::env;
 
-pub async fn connect_to_redis() -> redis::Connection {
+fn redis_connection_url() -> Result<String> {
     //format - host:port
-    let redis_host_name =
-        env::var("REDIS_HOSTNAME").expect("Missing environment variable REDIS_HOSTNAME");
+    let redis_host_name = env::var("REDIS_HOSTNAME")
+        .map_err(|_| Error::Config("Missing environment variable REDIS_HOSTNAME".to_string()))?;
     
     let redis_password = env::var("REDIS_PASSWORD").unwrap_or_default();
     
@@ -14,15 +16,14 @@ pub async fn connect_to_redis() -> redis::Connection {
         Err(_) => "redis",
     };
 
-    let redis_conn_url = format!(
+    Ok(format!(
         "{}://:{}@{}",
         uri_scheme, redis_password, redis_host_name
-    );
-
-    let client = redis::Client::open(redis_conn_url)
-        .expect("Invalid connection URL")
-        .get_connection()
-        .expect("failed to connect to Redis");
+    ))
+}
 
-    client
-}
\ No newline at end of file
+/// Opens an async connection manager that transparently reconnects and can be cloned across tasks.
+pub async fn connect_to_redis() -> Result<ConnectionManager> {
+    let client = redis::Client::open(redis_connection_url()?)?;
+    Ok(client.get_connection_manager().await?)
+}
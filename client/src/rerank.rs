@@ -0,0 +1,186 @@
+use crate::embed::CorpusMatch;
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::hashes::rerank_cache_key;
+use crate::store::VectorStore;
+
+use chatgpt::err::Error as ChatError;
+use chatgpt::types::{ChatMessage, Role};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::Semaphore;
+
+/// Highest relevance score the chat model is asked to give.
+const MAX_SCORE: f32 = 10.0;
+
+/// The `retrieval.rerank` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RerankConfig {
+    /// Number of first-stage candidates scored by the chat model.
+    pub candidates: usize,
+    /// Candidates scored per chat request.
+    pub batch_size: usize,
+    /// Maximum number of chat requests in flight at once.
+    pub concurrency: usize,
+    /// System prompt asking for the scores.
+    pub instruction: String,
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        Self {
+            candidates: 20,
+            batch_size: 10,
+            concurrency: 4,
+            instruction: "You rate how relevant passages are to a question. Reply with only a JSON object \
+                mapping every passage number to a score from 0 (unrelated) to 10 (answers the question), \
+                e.g. {\"1\": 7, \"2\": 0}."
+                .to_string(),
+        }
+    }
+}
+
+/// Orders `candidates` by the relevance to `query` the chat model gives them, best first. Scores
+/// are cached in the store by model, query and chunk text, so only unseen chunks are sent, and
+/// every chunk's similarity is replaced by its score scaled to `0..=1`. Chunks the model leaves
+/// unscored rank last, and ties keep the order of `candidates`.
+///
+/// A batch whose reply holds no scores keeps its chunks where they were in `candidates`, with
+/// the similarity of the chunk ranked above them, while the other chunks are ranked around them.
+pub async fn rerank(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    query: &str,
+    candidates: Vec<CorpusMatch>,
+    config: &RerankConfig,
+) -> Result<Vec<CorpusMatch>> {
+    let keys: Vec<String> = candidates
+        .iter()
+        .map(|chunk| rerank_cache_key(embedder.chat_model(), query, &chunk.pair.text))
+        .collect();
+    let mut scores: HashMap<String, f32> = HashMap::new();
+    for (key, score) in keys.iter().zip(store.get_cached_scores(&keys).await?) {
+        if let Some(score) = score {
+            scores.insert(key.clone(), score);
+        }
+    }
+
+    // Copies of a chunk share one key and are scored once.
+    let mut unscored: Vec<usize> = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        if !scores.contains_key(key) && !unscored.iter().any(|&j| keys[j] == *key) {
+            unscored.push(i);
+        }
+    }
+    let (texts, batch_keys) = (&candidates, &keys);
+    let limiter = Semaphore::new(config.concurrency.max(1));
+    let limiter = &limiter;
+    let batches = unscored.chunks(config.batch_size.max(1)).map(|batch| async move {
+        // The limiter is never closed, so acquiring it can only wait.
+        let _permit = limiter.acquire().await;
+        let passages: Vec<&str> = batch.iter().map(|&i| texts[i].pair.text.as_str()).collect();
+        let keys = batch.iter().map(|&i| batch_keys[i].clone());
+        Ok::<_, Error>(match score_passages(embedder, query, &passages, config).await? {
+            Some(scored) => (keys.zip(scored).filter_map(|(key, score)| Some((key, score?))).collect(), vec![]),
+            None => (vec![], keys.collect()),
+        })
+    });
+    let mut fresh: Vec<(String, f32)> = Vec::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    for (scored, unparsed) in futures::future::try_join_all(batches).await? {
+        fresh.extend(scored);
+        failed.extend(unparsed);
+    }
+    store.cache_scores(&fresh).await?;
+    scores.extend(fresh);
+
+    // Chunks of failed batches stay in their slots; the others are ranked into the remaining ones.
+    let mut slots: Vec<Option<CorpusMatch>> = Vec::with_capacity(candidates.len());
+    let mut ranked: Vec<(Option<f32>, CorpusMatch)> = Vec::new();
+    for (key, mut chunk) in keys.iter().zip(candidates) {
+        if failed.contains(key) {
+            slots.push(Some(chunk));
+        } else {
+            let score = scores.get(key).copied();
+            chunk.pair.similarity = score.unwrap_or(0.0) / MAX_SCORE;
+            ranked.push((score, chunk));
+            slots.push(None);
+        }
+    }
+    ranked.sort_by(|a, b| b.0.unwrap_or(-1.0).total_cmp(&a.0.unwrap_or(-1.0)));
+    let mut ranked = ranked.into_iter().map(|(_, chunk)| chunk);
+    let kept: Vec<bool> = slots.iter().map(Option::is_some).collect();
+    let mut merged: Vec<CorpusMatch> = slots
+        .into_iter()
+        .map(|slot| slot.or_else(|| ranked.next()).expect("a ranked chunk for every free slot"))
+        .collect();
+
+    // Kept chunks take the similarity of the chunk ranked above them, or below them when they
+    // lead, so that similarities keep decreasing down the list.
+    let first_ranked = merged.iter().zip(&kept).find(|(_, &kept)| !kept);
+    if let Some(mut similarity) = first_ranked.map(|(chunk, _)| chunk.pair.similarity) {
+        for (chunk, &kept) in merged.iter_mut().zip(&kept) {
+            if kept {
+                chunk.pair.similarity = similarity;
+            } else {
+                similarity = chunk.pair.similarity;
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Asks the chat model to score `passages` against `query`, returning one score per passage, or
+/// `None` when the reply holds no scores.
+async fn score_passages(
+    embedder: &Embedder,
+    query: &str,
+    passages: &[&str],
+    config: &RerankConfig,
+) -> Result<Option<Vec<Option<f32>>>> {
+    let mut content = format!("Question: {}", query);
+    for (i, passage) in passages.iter().enumerate() {
+        content.push_str(&format!("\n\nPassage {}:\n{}", i + 1, passage));
+    }
+    let messages = vec![
+        ChatMessage {
+            role: Role::System,
+            content: config.instruction.clone(),
+        },
+        ChatMessage { role: Role::User, content },
+    ];
+    let response = embedder.client().send_history(&messages).await?;
+    Ok(parse_scores(&response.message().content, passages.len()).ok())
+}
+
+/// Reads the JSON object of scores from a reply, clamping them to `0..=10`. Passages the object
+/// leaves out get no score.
+fn parse_scores(reply: &str, count: usize) -> Result<Vec<Option<f32>>> {
+    let object = reply
+        .find('{')
+        .zip(reply.rfind('}'))
+        .and_then(|(start, end)| serde_json::from_str::<HashMap<String, Value>>(reply.get(start..=end)?).ok())
+        .ok_or_else(|| ChatError::ParsingError(format!("The chat model returned no relevance scores: {}", reply)))?;
+    Ok((1..=count)
+        .map(|n| {
+            object
+                .get(&n.to_string())
+                .and_then(Value::as_f64)
+                .map(|score| (score as f32).clamp(0.0, MAX_SCORE))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scores() {
+        let reply = "Scores:\n```json\n{\"1\": 7, \"2\": 12.5, \"4\": \"high\"}\n```";
+        assert_eq!(vec![Some(7.0), Some(10.0), None, None], parse_scores(reply, 4).unwrap());
+        assert!(parse_scores("All passages are relevant.", 2).is_err());
+    }
+}
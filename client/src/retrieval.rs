@@ -0,0 +1,301 @@
+use crate::embed::{search_similar_chunks, CorpusMatch};
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::math::cosine_similarity;
+use crate::rerank::{rerank, RerankConfig};
+use crate::store::{SearchFilter, VectorStore};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Dampens the weight of top ranks in reciprocal rank fusion; 60 is the customary value.
+const RRF_K: f32 = 60.0;
+
+/// How chunks are ranked against a query.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrievalMode {
+    /// Cosine similarity of the embeddings.
+    #[default]
+    Vector,
+    /// BM25 over the chunk text, for exact identifiers, part numbers and names.
+    Keyword,
+    /// Both rankings combined with reciprocal rank fusion.
+    Hybrid,
+}
+
+impl FromStr for RetrievalMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RetrievalMode> {
+        match s {
+            "vector" => Ok(RetrievalMode::Vector),
+            "keyword" => Ok(RetrievalMode::Keyword),
+            "hybrid" => Ok(RetrievalMode::Hybrid),
+            _ => Err(Error::Config(format!("Unknown retrieval mode {}; use vector, keyword or hybrid", s))),
+        }
+    }
+}
+
+/// The `retrieval` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrievalConfig {
+    pub mode: RetrievalMode,
+    /// Share of the keyword ranking in hybrid mode, from 0 (vector only) to 1 (keyword only).
+    pub keyword_weight: f32,
+    /// Chunks taken from each ranking before they are fused or re-ranked, at least the number
+    /// retrieved.
+    pub candidates: usize,
+    /// Re-ranks the candidates with maximal marginal relevance when set, from 0 (most diverse)
+    /// to 1 (most relevant), so that overlapping neighbours do not crowd out other material.
+    pub mmr_lambda: Option<f32>,
+    /// Has the chat model score the best candidates when set, and ranks them by that score.
+    pub rerank: Option<RerankConfig>,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            mode: RetrievalMode::Vector,
+            keyword_weight: 0.5,
+            candidates: 50,
+            mmr_lambda: None,
+            rerank: None,
+        }
+    }
+}
+
+/// Overrides of the `retrieval` section, given on the command line or in a request body.
+#[derive(Debug, Default, Clone, PartialEq, Args, Serialize, Deserialize)]
+pub struct RetrievalArgs {
+    /// Ranks chunks by vector, keyword or hybrid; defaults to `retrieval.mode` in the config file
+    #[clap(long)]
+    #[serde(default)]
+    pub mode: Option<RetrievalMode>,
+    /// Diversifies the chunks with maximal marginal relevance, from 0 (most diverse) to 1 (most relevant)
+    #[clap(long)]
+    #[serde(default)]
+    pub mmr_lambda: Option<f32>,
+    /// Has the chat model rerank the candidates, with `retrieval.rerank` settings if configured;
+    /// `--rerank=false` turns configured reranking off
+    #[clap(long, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    #[serde(default)]
+    pub rerank: Option<bool>,
+}
+
+impl RetrievalArgs {
+    /// Returns `config` with the overrides applied. Turning reranking on keeps the configured
+    /// rerank settings, or uses the default ones.
+    pub fn apply(&self, config: &RetrievalConfig) -> RetrievalConfig {
+        let mut config = config.clone();
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if self.mmr_lambda.is_some() {
+            config.mmr_lambda = self.mmr_lambda;
+        }
+        match self.rerank {
+            Some(true) if config.rerank.is_none() => config.rerank = Some(RerankConfig::default()),
+            Some(false) => config.rerank = None,
+            _ => {}
+        }
+        config
+    }
+}
+
+/// Returns the `k` chunks selected by `filter` that best match `query`, ranked as `config` says.
+/// Chunks found by keyword carry their BM25 score, fused chunks their fused score and reranked
+/// chunks their relevance score, as similarity. Reranking comes before MMR.
+pub async fn retrieve(
+    store: &dyn VectorStore,
+    embedder: &Embedder,
+    query: &str,
+    k: usize,
+    filter: &SearchFilter,
+    config: &RetrievalConfig,
+) -> Result<Vec<CorpusMatch>> {
+    let mut depth = match config.mmr_lambda {
+        Some(_) => config.candidates.max(k),
+        None => k,
+    };
+    if let Some(rerank) = &config.rerank {
+        depth = depth.max(rerank.candidates);
+    }
+    let mut ranked = match config.mode {
+        RetrievalMode::Vector => search_similar_chunks(store, embedder, query, depth, filter).await?,
+        RetrievalMode::Keyword => store.keyword_search(query, depth, filter).await?,
+        RetrievalMode::Hybrid => {
+            let candidates = config.candidates.max(k);
+            let by_vector = search_similar_chunks(store, embedder, query, candidates, filter).await?;
+            let by_keyword = store.keyword_search(query, candidates, filter).await?;
+            reciprocal_rank_fusion(by_vector, by_keyword, config.keyword_weight)
+        }
+    };
+    if let Some(config) = &config.rerank {
+        ranked.truncate(config.candidates.max(k));
+        ranked = rerank(store, embedder, query, ranked, config).await?;
+    }
+    if let Some(lambda) = config.mmr_lambda {
+        return Ok(maximal_marginal_relevance(ranked, k, lambda));
+    }
+    ranked.truncate(k);
+    Ok(ranked)
+}
+
+/// Picks `k` of the ranked `candidates`, each time taking the one with the best trade-off between
+/// its relevance and its greatest cosine similarity to the chunks already picked. Relevance is
+/// the candidate's score relative to the best one, so that it is comparable in every mode;
+/// when some scores are negative it is their distance below the best one, taken from 1.
+/// `lambda` is clamped to `0..=1`. The chunks keep their scores, in the order they were picked.
+pub fn maximal_marginal_relevance(candidates: Vec<CorpusMatch>, k: usize, lambda: f32) -> Vec<CorpusMatch> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    let best = candidates.iter().map(|c| c.pair.similarity).fold(f32::NEG_INFINITY, f32::max);
+    let worst = candidates.iter().map(|c| c.pair.similarity).fold(f32::INFINITY, f32::min);
+    let mut remaining: Vec<(CorpusMatch, f32, f32)> = candidates
+        .into_iter()
+        .map(|c| {
+            let similarity = c.pair.similarity;
+            let relevance = if worst < 0.0 {
+                similarity - best + 1.0
+            } else if best > 0.0 {
+                similarity / best
+            } else {
+                1.0
+            };
+            (c, relevance, 0.0)
+        })
+        .collect();
+
+    let mut picked: Vec<CorpusMatch> = Vec::new();
+    while picked.len() < k && !remaining.is_empty() {
+        let score = |&(_, relevance, redundancy): &(CorpusMatch, f32, f32)| {
+            lambda * relevance - (1.0 - lambda) * redundancy
+        };
+        // The first of equally scored candidates wins, so ties keep the ranking's order.
+        let mut next = 0;
+        for i in 1..remaining.len() {
+            if score(&remaining[i]) > score(&remaining[next]) {
+                next = i;
+            }
+        }
+        let (chunk, _, _) = remaining.remove(next);
+        for (candidate, _, redundancy) in remaining.iter_mut() {
+            *redundancy = redundancy.max(cosine_similarity(&candidate.pair.embedding, &chunk.pair.embedding));
+        }
+        picked.push(chunk);
+    }
+    picked
+}
+
+/// Merges two rankings of chunks, scoring each chunk by the weighted sum of `1 / (60 + rank)`
+/// over the rankings it appears in. `keyword_weight` is clamped to `0..=1`, the vector ranking
+/// getting the rest.
+pub fn reciprocal_rank_fusion(
+    by_vector: Vec<CorpusMatch>,
+    by_keyword: Vec<CorpusMatch>,
+    keyword_weight: f32,
+) -> Vec<CorpusMatch> {
+    let keyword_weight = keyword_weight.clamp(0.0, 1.0);
+    let rankings = [(by_vector, 1.0 - keyword_weight), (by_keyword, keyword_weight)];
+
+    let mut fused: Vec<CorpusMatch> = Vec::new();
+    let mut index: HashMap<(String, usize), usize> = HashMap::new();
+    for (ranking, weight) in rankings {
+        for (rank, chunk) in ranking.into_iter().enumerate() {
+            let score = weight / (RRF_K + rank as f32 + 1.0);
+            let key = (chunk.file.hash.clone(), chunk.position);
+            match index.get(&key) {
+                Some(&i) => fused[i].pair.similarity += score,
+                None => {
+                    index.insert(key, fused.len());
+                    let mut chunk = chunk;
+                    chunk.pair.similarity = score;
+                    fused.push(chunk);
+                }
+            }
+        }
+    }
+    // Stable, so ties keep the vector ranking's order.
+    fused.sort_by(|a, b| b.pair.similarity.total_cmp(&a.pair.similarity));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::{EmbeddingPair, FileHash};
+
+    fn chunk(hash: &str, position: usize) -> CorpusMatch {
+        CorpusMatch {
+            file: FileHash {
+                hash: hash.to_string(),
+                filename: format!("{}.txt", hash),
+                ..Default::default()
+            },
+            position,
+            pair: EmbeddingPair::new(format!("{}#{}", hash, position), vec![]),
+        }
+    }
+
+    fn labels(chunks: &[CorpusMatch]) -> Vec<String> {
+        chunks.iter().map(|c| c.pair.text.clone()).collect()
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let by_vector = || vec![chunk("a", 0), chunk("b", 0), chunk("c", 0)];
+        let by_keyword = || vec![chunk("c", 0), chunk("d", 1)];
+
+        // Found by both rankings, c overtakes a chunk found by only one of them; b and d tie.
+        let fused = reciprocal_rank_fusion(by_vector(), by_keyword(), 0.5);
+        assert_eq!(vec!["c#0", "a#0", "b#0", "d#1"], labels(&fused));
+        let expected = 0.5 / 61.0 + 0.5 / 63.0;
+        assert!((fused[0].pair.similarity - expected).abs() < 1e-6);
+
+        // The weight shifts the result towards one ranking.
+        assert_eq!(vec!["a#0", "b#0", "c#0", "d#1"], labels(&reciprocal_rank_fusion(by_vector(), by_keyword(), 0.0))[..4]);
+        assert_eq!(vec!["c#0", "d#1"], labels(&reciprocal_rank_fusion(by_vector(), by_keyword(), 1.0))[..2]);
+    }
+
+    #[test]
+    fn test_maximal_marginal_relevance() {
+        let with = |mut chunk: CorpusMatch, similarity: f32, embedding: Vec<f32>| {
+            chunk.pair.similarity = similarity;
+            chunk.pair.embedding = embedding;
+            chunk
+        };
+        let candidates = || {
+            vec![
+                with(chunk("a", 0), 0.9, vec![1.0, 0.0]),
+                with(chunk("a", 1), 0.88, vec![0.99, 0.1]),
+                with(chunk("b", 0), 0.7, vec![0.0, 1.0]),
+            ]
+        };
+
+        // Relevance alone keeps the ranking; a lower lambda skips the near duplicate of a#0.
+        assert_eq!(vec!["a#0", "a#1"], labels(&maximal_marginal_relevance(candidates(), 2, 1.0)));
+        let diverse = maximal_marginal_relevance(candidates(), 3, 0.5);
+        assert_eq!(vec!["a#0", "b#0", "a#1"], labels(&diverse));
+        assert_eq!(0.7, diverse[1].pair.similarity);
+        assert!(maximal_marginal_relevance(vec![], 3, 0.5).is_empty());
+
+        // Negative similarities, as L2 scores give, still favour the closest chunk.
+        let negative = vec![
+            with(chunk("a", 0), -0.2, vec![1.0, 0.0]),
+            with(chunk("b", 0), -1.5, vec![0.0, 1.0]),
+            with(chunk("c", 0), -0.4, vec![0.7, 0.7]),
+        ];
+        assert_eq!(vec!["a#0", "c#0", "b#0"], labels(&maximal_marginal_relevance(negative, 3, 0.8)));
+    }
+
+    #[test]
+    fn test_retrieval_mode() {
+        assert_eq!(RetrievalMode::Hybrid, "hybrid".parse().unwrap());
+        assert!("fuzzy".parse::<RetrievalMode>().is_err());
+        let config: RetrievalConfig = serde_yaml::from_str("mode: keyword").unwrap();
+        assert_eq!((RetrievalMode::Keyword, 50), (config.mode, config.candidates));
+    }
+}
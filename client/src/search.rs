@@ -1,8 +1,9 @@
-use std::collections::BTreeSet;
+use glob::{Pattern, PatternError};
+use std::collections::{BTreeSet, HashSet};
 use std::fmt;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Eq)]
 pub struct FileEntry {
@@ -15,12 +16,14 @@ pub struct FileEntry {
 }
 pub struct FileSearchContext {
     tree: BTreeSet<FileEntry>,
+    skipped: Vec<(PathBuf, io::Error)>,
 }
 
 impl FileSearchContext {
     pub fn new() -> Self {
         Self {
             tree: BTreeSet::new(),
+            skipped: Vec::new(),
         }
     }
 
@@ -31,6 +34,11 @@ impl FileSearchContext {
     pub fn get_files(&self) -> &BTreeSet<FileEntry> {
         &self.tree
     }
+
+    /// Directories a walk could not read and left out, with the reason.
+    pub fn skipped_dirs(&self) -> &[(PathBuf, io::Error)] {
+        &self.skipped
+    }
 }
 
 impl Default for FileSearchContext {
@@ -119,3 +127,152 @@ pub fn path_get_next_search_paths(
     }
     Ok(results)
 }
+
+/// How symbolic links are treated while walking a directory tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinked files and directories are ignored.
+    Skip,
+    /// Symlinks are followed; directories already visited are not walked twice.
+    Follow,
+}
+
+/// Filters applied by [`path_walk_files`].
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// Glob patterns, relative to the walk root, a file must match. Empty matches everything.
+    pub include: Vec<Pattern>,
+    /// Glob patterns, relative to the walk root, excluding files and directories.
+    pub exclude: Vec<Pattern>,
+    pub symlinks: SymlinkPolicy,
+    /// Files larger than this many bytes are skipped.
+    pub max_size: Option<u64>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::Skip,
+            max_size: None,
+        }
+    }
+}
+
+impl WalkOptions {
+    /// Builds walk options from textual glob patterns.
+    pub fn from_globs(
+        include: &[String],
+        exclude: &[String],
+        symlinks: SymlinkPolicy,
+        max_size: Option<u64>,
+    ) -> std::result::Result<Self, PatternError> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|g| Pattern::new(g))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            symlinks,
+            max_size,
+        })
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn is_included(&self, relative: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative))
+    }
+}
+
+/// Recursively collects the regular files below `root` that pass the walk options.
+pub fn path_walk_files(
+    ctx: &mut FileSearchContext,
+    root: &Path,
+    options: &WalkOptions,
+) -> io::Result<Vec<FileEntry>> {
+    let mut results: Vec<FileEntry> = Vec::new();
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut pending: Vec<PathBuf> = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        // An unreadable subdirectory is recorded and skipped rather than ending the walk; only
+        // the root must be readable.
+        let listing = fs::canonicalize(&dir).and_then(|canonical| {
+            if !visited.insert(canonical.to_string_lossy().into_owned()) {
+                return Ok(vec![]);
+            }
+            path_get_file_list(ctx, &dir)
+        });
+        let entries = match listing {
+            Ok(entries) => entries,
+            Err(e) if dir != root => {
+                ctx.skipped.push((dir, e));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = Path::new(&entry.path);
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            if options.is_excluded(relative) {
+                continue;
+            }
+            if entry.is_symlink && options.symlinks == SymlinkPolicy::Skip {
+                continue;
+            }
+            if entry.is_dir {
+                pending.push(path.to_path_buf());
+                continue;
+            }
+            if !options.is_included(relative) {
+                continue;
+            }
+            if options.max_size.is_some_and(|max| entry.size > max) {
+                continue;
+            }
+            results.push(entry);
+        }
+    }
+    results.sort();
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_walk_files() {
+        let root = std::env::temp_dir().join(format!("dbsearch-walk-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("skip")).unwrap();
+        fs::write(root.join("top.md"), "top").unwrap();
+        fs::write(root.join("a/b/deep.txt"), "deep").unwrap();
+        fs::write(root.join("a/large.txt"), "x".repeat(64)).unwrap();
+        fs::write(root.join("skip/hidden.txt"), "hidden").unwrap();
+
+        let options = WalkOptions::from_globs(
+            &["**/*.txt".to_string(), "*.md".to_string()],
+            &["skip".to_string()],
+            SymlinkPolicy::Skip,
+            Some(16),
+        )
+        .unwrap();
+        let mut ctx = FileSearchContext::new();
+        let names: Vec<String> = path_walk_files(&mut ctx, &root, &options)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec!["deep.txt".to_string(), "top.md".to_string()], names);
+    }
+}
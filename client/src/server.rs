@@ -0,0 +1,301 @@
+use crate::answer::prepare_prompt;
+use crate::chunker::TextChunker;
+use crate::config::DBSearchConfig;
+use crate::embed::{CorpusMatch, FileHash, IndexEvent};
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::index::{index_paths, IndexSummary};
+use crate::prompt::{chunk_label, PromptConfig};
+use crate::retrieval::{retrieve, RetrievalArgs, RetrievalConfig};
+use crate::search::WalkOptions;
+use crate::store::{SearchFilter, VectorStore};
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum::{Json, Router};
+use chatgpt::types::ResponseChunk;
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Everything the HTTP handlers share: the store, the models and the prompt settings.
+pub struct ServerState {
+    pub store: Arc<dyn VectorStore>,
+    pub embedder: Embedder,
+    pub chunker: TextChunker,
+    pub agent_prompt: String,
+    pub prompt: PromptConfig,
+    pub retrieval: RetrievalConfig,
+    /// Maximum number of embedding requests in flight per indexing request.
+    pub concurrency: usize,
+    /// Directory that every path sent for indexing must lie in.
+    pub root: PathBuf,
+}
+
+impl ServerState {
+    /// Opens the store and creates the models described by the configuration file, only
+    /// indexing files below `root`.
+    pub async fn from_config(config: &DBSearchConfig, root: PathBuf) -> Result<ServerState> {
+        Ok(ServerState {
+            store: config.open_store().await?,
+            embedder: config.embedder()?,
+            chunker: config.chunker()?,
+            agent_prompt: config.agent_prompt.clone(),
+            prompt: config.prompt.clone(),
+            retrieval: config.retrieval.clone(),
+            concurrency: config.concurrency,
+            root,
+        })
+    }
+}
+
+/// Body of `POST /documents`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexRequest {
+    /// Files and directories to index, relative to the server's working directory. They must
+    /// lie in the server's root directory.
+    pub paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub collection: Option<String>,
+}
+
+/// Query string of `GET /documents`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListQuery {
+    pub collection: Option<String>,
+}
+
+/// Response of `DELETE /documents/{hash}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deleted {
+    pub hash: String,
+    /// Number of chunks removed.
+    pub chunks: usize,
+}
+
+/// Body of `POST /search` and `POST /ask`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    /// Number of chunks to retrieve; defaults to `prompt.top_k` in the config file.
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub collection: Option<String>,
+    /// `mode`, `mmr_lambda` and `rerank`, overriding the server's retrieval settings.
+    #[serde(flatten)]
+    pub retrieval: RetrievalArgs,
+}
+
+/// A retrieved chunk as returned by the API, without its embedding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// Citation label of the chunk, e.g. `[notes.md#3]`.
+    pub label: String,
+    pub hash: String,
+    pub filename: String,
+    pub position: usize,
+    pub page: Option<u32>,
+    pub similarity: f32,
+    pub text: String,
+}
+
+impl From<&CorpusMatch> for SearchHit {
+    fn from(chunk: &CorpusMatch) -> SearchHit {
+        SearchHit {
+            label: chunk_label(chunk),
+            hash: chunk.file.hash.clone(),
+            filename: chunk.file.filename.clone(),
+            position: chunk.position,
+            page: chunk.pair.metadata.page,
+            similarity: chunk.pair.similarity,
+            text: chunk.pair.text.clone(),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Config(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+/// Routes of the HTTP/JSON API.
+///
+/// * `POST /documents` indexes files and directories and returns an [`IndexSummary`].
+/// * `GET /documents?collection=` lists the indexed documents.
+/// * `DELETE /documents/{hash}` removes a document's chunks.
+/// * `POST /search` returns the chunks that best match a query.
+/// * `POST /ask` answers a query as server-sent events: `sources` with the chunks sent to the
+///   model, a `delta` per piece of the answer, then `done`, or `error` if the model fails.
+pub fn router(state: Arc<ServerState>) -> Router {
+    Router::new()
+        .route("/documents", post(index_documents).get(list_documents))
+        .route("/documents/:hash", delete(delete_document))
+        .route("/search", post(search))
+        .route("/ask", post(ask))
+        .with_state(state)
+}
+
+/// Serves the API on `addr` until ctrl-c is pressed.
+pub async fn serve(state: ServerState, addr: SocketAddr) -> Result<()> {
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|e| Error::Config(format!("Unable to listen on {}: {}", addr, e)))?;
+    println!("Listening on http://{}", addr);
+    server
+        .serve(router(Arc::new(state)).into_make_service())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))
+}
+
+/// Selects the chunks of a collection, which must not be empty, or of every document.
+async fn collection_filter(store: &dyn VectorStore, collection: Option<&str>) -> Result<SearchFilter> {
+    let mut filter = SearchFilter::default();
+    if let Some(name) = collection {
+        filter.file_hashes = store.list_files(Some(name)).await?.into_iter().map(|file| file.hash).collect();
+        if filter.file_hashes.is_empty() {
+            return Err(Error::NotFound(format!("No indexed documents in collection {}", name)));
+        }
+    }
+    Ok(filter)
+}
+
+/// Server-sent event carrying plain text; carriage returns are not allowed in event data.
+fn text_event(name: &str, data: &str) -> Event {
+    Event::default().event(name).data(data.replace('\r', ""))
+}
+
+fn ignore_events(_: &str, _: IndexEvent) {}
+
+async fn index_documents(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<IndexRequest>,
+) -> Result<Json<IndexSummary>> {
+    // Resolving symlinks and `..` first keeps requests from reaching outside the root.
+    let root = state.root.canonicalize()?;
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in &request.paths {
+        let resolved = path
+            .canonicalize()
+            .map_err(|_| Error::NotFound(path.display().to_string()))?;
+        if !resolved.starts_with(&root) {
+            return Err(Error::Config(format!("{} is outside of {}", path.display(), root.display())));
+        }
+        paths.push(resolved);
+    }
+    let summary = index_paths(
+        state.store.as_ref(),
+        &state.embedder,
+        &state.chunker,
+        &paths,
+        &WalkOptions::default(),
+        request.collection.as_deref(),
+        state.concurrency,
+        &ignore_events,
+    )
+    .await?;
+    Ok(Json(summary))
+}
+
+async fn list_documents(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<FileHash>>> {
+    Ok(Json(state.store.list_files(query.collection.as_deref()).await?))
+}
+
+/// Documents still pending or that failed to index have no file record yet, so their registry
+/// entries and stored chunks count too.
+async fn delete_document(State(state): State<Arc<ServerState>>, Path(hash): Path<String>) -> Result<Json<Deleted>> {
+    let registered = state.store.get_file(&hash).await?.is_some()
+        || state.store.list_documents().await?.iter().any(|record| record.hash == hash);
+    let chunks = state.store.delete_file(&hash).await?;
+    if !registered && chunks == 0 {
+        return Err(Error::NotFound(format!("No indexed document with hash {}", hash)));
+    }
+    Ok(Json(Deleted { hash, chunks }))
+}
+
+async fn search(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<Vec<SearchHit>>> {
+    let filter = collection_filter(state.store.as_ref(), request.collection.as_deref()).await?;
+    let top_k = request.top_k.unwrap_or(state.prompt.top_k);
+    let retrieval = request.retrieval.apply(&state.retrieval);
+    let chunks = retrieve(state.store.as_ref(), &state.embedder, &request.query, top_k, &filter, &retrieval).await?;
+    Ok(Json(chunks.iter().map(SearchHit::from).collect()))
+}
+
+/// Retrieves the sources before answering, so that retrieval failures get a proper status code;
+/// only the answer itself is streamed.
+async fn ask(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    let filter = collection_filter(state.store.as_ref(), request.collection.as_deref()).await?;
+    let mut config = state.prompt.clone();
+    if let Some(top_k) = request.top_k {
+        config.top_k = top_k;
+    }
+    let prompt = prepare_prompt(
+        state.store.as_ref(),
+        &state.embedder,
+        &state.agent_prompt,
+        &request.query,
+        &config,
+        &request.retrieval.apply(&state.retrieval),
+        state.chunker.tokenizer(),
+        &filter,
+    )
+    .await?;
+
+    let (events, receiver) = mpsc::unbounded();
+    let sources: Vec<SearchHit> = prompt.sources.iter().map(SearchHit::from).collect();
+    let _ = events.unbounded_send(text_event("sources", &json!(sources).to_string()));
+    tokio::spawn(async move {
+        let stream = match state.embedder.client().send_history_streaming(&prompt.messages).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = events.unbounded_send(text_event("error", &Error::from(e).to_string()));
+                return;
+            }
+        };
+        futures::pin_mut!(stream);
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                // Stop early once the client has gone away.
+                Ok(ResponseChunk::Content { delta, response_index: 0 })
+                    if events.unbounded_send(text_event("delta", &delta)).is_err() =>
+                {
+                    return;
+                }
+                Ok(ResponseChunk::Done) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = events.unbounded_send(text_event("error", &Error::from(e).to_string()));
+                    return;
+                }
+            }
+        }
+        let _ = events.unbounded_send(text_event("done", ""));
+    });
+    Ok(Sse::new(receiver.map(Ok)))
+}
@@ -0,0 +1,195 @@
+use crate::embed::{CorpusMatch, EmbeddingPair, FileHash};
+use crate::local_store::LocalStore;
+use crate::redis_store::RedisStore;
+use crate::vector_index::VectorIndexConfig;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Result returned by vector store operations.
+pub type StoreResult<T> = crate::error::Result<T>;
+
+/// Restricts a similarity search to a subset of the stored chunks.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchFilter {
+    /// Only chunks of files with one of these SHA-256 hashes. Empty matches every file.
+    pub file_hashes: Vec<String>,
+    /// Only chunks of the file with this exact name.
+    pub filename: Option<String>,
+    /// Only chunks whose position within their file lies in this inclusive range.
+    pub positions: Option<(usize, usize)>,
+}
+
+impl SearchFilter {
+    /// Returns true if a chunk of `file` at `position` passes the filter.
+    pub fn matches(&self, file: &FileHash, position: usize) -> bool {
+        if !self.file_hashes.is_empty() && !self.file_hashes.contains(&file.hash) {
+            return false;
+        }
+        if self.filename.as_ref().is_some_and(|f| *f != file.filename) {
+            return false;
+        }
+        match self.positions {
+            Some((first, last)) => first <= position && position <= last,
+            None => true,
+        }
+    }
+}
+
+/// Totals reported by [`VectorStore::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreStats {
+    /// Fully indexed files.
+    pub files: usize,
+    /// Stored chunks, including those of files still being indexed.
+    pub chunks: usize,
+    pub collections: usize,
+}
+
+/// Indexing state of a document in the registry.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentStatus {
+    /// Embedding has started; some chunks may already be stored.
+    #[default]
+    Pending,
+    /// Every chunk is stored and the file is registered.
+    Complete,
+    /// Embedding failed; the chunks stored so far are reused on the next run.
+    Failed,
+}
+
+/// Registry entry tracking the current content of a file on disk.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentRecord {
+    /// Canonical path of the file, see [`document_key`].
+    pub path: String,
+    /// SHA-256 hash of the content being indexed, or indexed once complete.
+    pub hash: String,
+    pub status: DocumentStatus,
+    /// Number of chunks, known once complete.
+    pub chunks: usize,
+    /// Hash of the earlier content, still searchable until the new content is complete.
+    pub previous_hash: Option<String>,
+    /// Collections the file was added to, kept when its content changes.
+    pub collections: Vec<String>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+}
+
+/// Registry key of a file: its canonical path, or the path as given if it cannot be resolved.
+pub fn document_key(path: &Path) -> String {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().into_owned()
+}
+
+/// Persists chunk embeddings and answers similarity searches over them.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Stores the chunk at `position` within `file`, replacing any previous one.
+    async fn store_chunk(&self, file: &FileHash, position: usize, pair: &EmbeddingPair) -> StoreResult<()>;
+
+    /// Marks a file as fully indexed and records its filename.
+    async fn register_file(&self, file: &FileHash) -> StoreResult<()>;
+
+    /// Checks whether a file with the given SHA-256 hash has been fully indexed.
+    async fn is_processed(&self, file_sha256_hash: &str) -> StoreResult<bool>;
+
+    /// Returns the registration of a fully indexed file, including the model it was embedded with.
+    async fn get_file(&self, file_sha256_hash: &str) -> StoreResult<Option<FileHash>>;
+
+    /// Loads every stored chunk of a file, ordered by position.
+    async fn load_file_chunks(&self, file_sha256_hash: &str) -> StoreResult<Vec<EmbeddingPair>>;
+
+    /// Lists the registered files, optionally restricted to a named collection.
+    async fn list_files(&self, collection: Option<&str>) -> StoreResult<Vec<FileHash>>;
+
+    /// Adds an indexed file to a named collection.
+    async fn add_to_collection(&self, collection: &str, file_sha256_hash: &str) -> StoreResult<()>;
+
+    /// Removes every chunk of a file, its registration, its collection memberships and the
+    /// registry entries pointing at it, returning the number of chunks removed.
+    async fn delete_file(&self, file_sha256_hash: &str) -> StoreResult<usize>;
+
+    /// Removes every chunk of a file but keeps its registration, collection memberships and
+    /// registry entries, returning the number of chunks removed.
+    async fn delete_chunks(&self, file_sha256_hash: &str) -> StoreResult<usize>;
+
+    /// Returns the registry entry of the file at `path`, a [`document_key`].
+    async fn get_document(&self, path: &str) -> StoreResult<Option<DocumentRecord>>;
+
+    /// Creates or replaces the registry entry of `record.path`.
+    async fn put_document(&self, record: &DocumentRecord) -> StoreResult<()>;
+
+    /// Removes the registry entry of the file at `path`, leaving its chunks alone.
+    async fn remove_document(&self, path: &str) -> StoreResult<()>;
+
+    /// Lists every registry entry, ordered by path.
+    async fn list_documents(&self) -> StoreResult<Vec<DocumentRecord>>;
+
+    /// Looks up cached embeddings by [`embedding_cache_key`](crate::hashes::embedding_cache_key),
+    /// returning one entry per key.
+    async fn get_cached_embeddings(&self, keys: &[String]) -> StoreResult<Vec<Option<Vec<f32>>>>;
+
+    /// Adds embeddings to the cache. Cached embeddings outlive the files they came from.
+    async fn cache_embeddings(&self, entries: &[(String, Vec<f32>)]) -> StoreResult<()>;
+
+    /// Looks up cached relevance scores by [`rerank_cache_key`](crate::hashes::rerank_cache_key),
+    /// returning one entry per key.
+    async fn get_cached_scores(&self, keys: &[String]) -> StoreResult<Vec<Option<f32>>>;
+
+    /// Adds relevance scores to the rerank cache.
+    async fn cache_scores(&self, entries: &[(String, f32)]) -> StoreResult<()>;
+
+    /// Counts the registered files, stored chunks and collections.
+    async fn stats(&self) -> StoreResult<StoreStats>;
+
+    /// Returns the `k` chunks most similar to `embedding`, most similar first.
+    async fn search(&self, embedding: &[f32], k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>>;
+
+    /// Returns the `k` chunks whose text best matches the words of `query` by BM25, best first,
+    /// with their score as similarity.
+    async fn keyword_search(&self, query: &str, k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>>;
+}
+
+/// Storage backend selected in the configuration file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// Redis Stack, searched through a RediSearch vector index.
+    #[default]
+    Redis,
+    /// Flat files in a local directory, searched in-process.
+    Local,
+}
+
+/// The `store` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    pub backend: StoreBackend,
+    /// Directory used by the local backend.
+    pub path: PathBuf,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Redis,
+            path: PathBuf::from("dbsearch-store"),
+        }
+    }
+}
+
+/// Opens the vector store selected by `config`.
+pub async fn open_store(
+    config: &StoreConfig,
+    index_config: &VectorIndexConfig,
+) -> StoreResult<Arc<dyn VectorStore>> {
+    Ok(match config.backend {
+        StoreBackend::Redis => Arc::new(RedisStore::connect(index_config.clone()).await?),
+        StoreBackend::Local => Arc::new(LocalStore::open(&config.path)?),
+    })
+}
@@ -1,3 +1,5 @@
+use crate::chunker::{validate_chunk_params, ChunkError};
+
 use regex::Regex;
 
 #[derive(PartialEq, Debug, Clone)]
@@ -16,22 +18,26 @@ impl TextSummary {
         self.text = replaced_text.to_string();
     }
 
+    /// Splits the text into chunks of `chunk_size` words, consecutive chunks sharing
+    /// `chunk_overlap` words. The last chunk holds the remaining words and may be shorter.
     pub fn tokenize_words_into_chunks(
         &mut self,
         chunk_size: usize,
         chunk_overlap: usize,
-    ) -> Vec<String> {
+    ) -> Result<Vec<String>, ChunkError> {
+        validate_chunk_params(chunk_size, chunk_overlap)?;
         self.replace_escape_sequences();
 
         let tokens: Vec<_> = self.text.split_whitespace().map(String::from).collect();
         let mut buckets = Vec::new();
         for i in (0..tokens.len()).step_by(chunk_size - chunk_overlap) {
-            let bucket_end = i + chunk_size;
-            if bucket_end <= tokens.len() {
-                buckets.push(tokens[i..bucket_end].to_vec().join(" "));
+            let bucket_end = (i + chunk_size).min(tokens.len());
+            buckets.push(tokens[i..bucket_end].join(" "));
+            if bucket_end == tokens.len() {
+                break;
             }
         }
-        buckets
+        Ok(buckets)
     }
 }
 
@@ -43,8 +49,8 @@ mod tests {
     fn test_text_summary_1() {
         let str_1 = String::from("A B C D E F G");
         let mut summary: TextSummary = TextSummary::new(str_1);
-        summary.tokenize_words_into_chunks(3, 1);
-        let chunks = summary.tokenize_words_into_chunks(3, 1);
+        summary.tokenize_words_into_chunks(3, 1).unwrap();
+        let chunks = summary.tokenize_words_into_chunks(3, 1).unwrap();
         let expected = vec![
             "A B C".to_string(),
             "C D E".to_string(),
@@ -53,6 +59,37 @@ mod tests {
         assert_eq!(expected, chunks);
     }
 
+    #[test]
+    fn test_text_summary_keeps_tail() {
+        let mut summary = TextSummary::new(String::from("A B C D E F G H"));
+        assert_eq!(vec!["A B C", "C D E", "E F G", "G H"], summary.tokenize_words_into_chunks(3, 1).unwrap());
+
+        let mut short = TextSummary::new(String::from("A B"));
+        assert_eq!(vec!["A B"], short.tokenize_words_into_chunks(400, 100).unwrap());
+
+        assert!(short.tokenize_words_into_chunks(3, 3).is_err());
+        assert!(short.tokenize_words_into_chunks(0, 0).is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_every_word_lands_in_a_chunk(
+            words in proptest::collection::vec("[a-z]{1,5}", 0..60),
+            chunk_size in 1usize..10,
+            overlap_ratio in 0.0f64..1.0,
+        ) {
+            let chunk_overlap = (chunk_size as f64 * overlap_ratio) as usize;
+            let mut summary = TextSummary::new(words.join(" "));
+            let chunks = summary.tokenize_words_into_chunks(chunk_size, chunk_overlap).unwrap();
+
+            let chunked: Vec<&str> = chunks.iter().flat_map(|chunk| chunk.split(' ')).collect();
+            for word in &words {
+                proptest::prop_assert!(chunked.contains(&word.as_str()));
+            }
+            proptest::prop_assert_eq!(words.last().map(String::as_str), chunked.last().copied());
+        }
+    }
+
     /*
     #[test]
     fn test_text_summary_2() {
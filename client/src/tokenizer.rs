@@ -0,0 +1,192 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Pre-tokenization pattern of cl100k_base, minus the `\s+(?!\S)` lookahead the regex crate
+/// lacks; [`Tokenizer::split`] restores that behaviour.
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+/// Byte-pair encoding tokenizer using a tiktoken-format vocabulary, such as `cl100k_base.tiktoken`.
+///
+/// Each line of the vocabulary holds a base64-encoded token and its rank; lower ranks merge first.
+pub struct Tokenizer {
+    encoder: HashMap<Vec<u8>, u32>,
+    decoder: HashMap<u32, Vec<u8>>,
+    pattern: Regex,
+}
+
+impl Tokenizer {
+    /// Loads a tiktoken-format vocabulary file.
+    pub fn from_file(path: &Path) -> io::Result<Tokenizer> {
+        let vocabulary = fs::read_to_string(path)?;
+        Tokenizer::from_vocabulary(&vocabulary)
+    }
+
+    /// Parses the contents of a tiktoken-format vocabulary.
+    pub fn from_vocabulary(vocabulary: &str) -> io::Result<Tokenizer> {
+        let invalid = |line: usize, reason: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid vocabulary line {}: {}", line, reason))
+        };
+
+        let mut encoder: HashMap<Vec<u8>, u32> = HashMap::new();
+        for (i, line) in vocabulary.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid(i + 1, "expected a token and a rank".to_string()))?;
+            let token = STANDARD.decode(token).map_err(|e| invalid(i + 1, e.to_string()))?;
+            let rank = rank.trim().parse::<u32>().map_err(|e| invalid(i + 1, e.to_string()))?;
+            encoder.insert(token, rank);
+        }
+        if let Some(byte) = (0..=255u8).find(|b| !encoder.contains_key(&vec![*b])) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Vocabulary has no token for byte {:#04x}", byte),
+            ));
+        }
+
+        let decoder = encoder.iter().map(|(token, rank)| (*rank, token.clone())).collect();
+        Ok(Tokenizer {
+            encoder,
+            decoder,
+            pattern: Regex::new(CL100K_PATTERN).unwrap(),
+        })
+    }
+
+    /// Splits text into the pieces that are byte-pair encoded independently, as byte ranges.
+    fn split(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while let Some(found) = self.pattern.find_at(text, start) {
+            let mut end = found.end();
+            // Emulates `\s+(?!\S)`: a whitespace run followed by a word leaves its last
+            // character to be merged into that word.
+            let piece = found.as_str();
+            if end < text.len() && piece.chars().all(char::is_whitespace) && piece.chars().count() > 1 {
+                let last = piece.chars().next_back().unwrap();
+                if !matches!(last, '\r' | '\n') {
+                    end -= last.len_utf8();
+                }
+            }
+            pieces.push((found.start(), end));
+            start = end;
+        }
+        pieces
+    }
+
+    /// Byte-pair encodes one piece, returning each token with its length in bytes.
+    fn encode_piece(&self, piece: &[u8], tokens: &mut Vec<(u32, usize)>) {
+        if let Some(&rank) = self.encoder.get(piece) {
+            tokens.push((rank, piece.len()));
+            return;
+        }
+
+        // Boundaries of the current parts; merge the adjacent pair with the lowest rank until none is known.
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..bounds.len().saturating_sub(2))
+                .filter_map(|i| self.encoder.get(&piece[bounds[i]..bounds[i + 2]]).map(|rank| (*rank, i)))
+                .min();
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        for pair in bounds.windows(2) {
+            tokens.push((self.encoder[&piece[pair[0]..pair[1]]], pair[1] - pair[0]));
+        }
+    }
+
+    /// Encodes text into token ids.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.encode_with_offsets(text).into_iter().map(|(token, _)| token).collect()
+    }
+
+    /// Encodes text into token ids, each paired with the byte offset in `text` where it starts.
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<(u32, usize)> {
+        let mut encoded: Vec<(u32, usize)> = Vec::new();
+        let mut tokens: Vec<(u32, usize)> = Vec::new();
+        for (start, end) in self.split(text) {
+            tokens.clear();
+            self.encode_piece(&text.as_bytes()[start..end], &mut tokens);
+            let mut offset = start;
+            for &(token, len) in &tokens {
+                encoded.push((token, offset));
+                offset += len;
+            }
+        }
+        encoded
+    }
+
+    /// Decodes token ids back into text, replacing invalid UTF-8 and unknown ids.
+    pub fn decode(&self, tokens: &[u32]) -> String {
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .flat_map(|token| self.decoder.get(token).cloned().unwrap_or_default())
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Counts the tokens in a text.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.split(text)
+            .into_iter()
+            .map(|(start, end)| {
+                let mut tokens = Vec::new();
+                self.encode_piece(&text.as_bytes()[start..end], &mut tokens);
+                tokens.len()
+            })
+            .sum()
+    }
+}
+
+/// Counts tokens with `tokenizer`, or estimates them at four bytes per token without one.
+pub fn count_tokens(tokenizer: Option<&Tokenizer>, text: &str) -> usize {
+    match tokenizer {
+        Some(tokenizer) => tokenizer.count_tokens(text),
+        None => chatgpt::config::estimate_tokens(text),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Every single byte, followed by a handful of merges.
+    pub(crate) fn test_tokenizer() -> Tokenizer {
+        let mut vocabulary = String::new();
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        for merged in ["he", "ll", "hell", "hello", " w", "or", " wor", " world"] {
+            tokens.push(merged.as_bytes().to_vec());
+        }
+        for (rank, token) in tokens.iter().enumerate() {
+            vocabulary.push_str(&format!("{} {}\n", STANDARD.encode(token), rank));
+        }
+        Tokenizer::from_vocabulary(&vocabulary).unwrap()
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let tokenizer = test_tokenizer();
+        let hello = tokenizer.encode("hello world");
+        assert_eq!(vec![259, 263], hello);
+        assert_eq!("hello world", tokenizer.decode(&hello));
+
+        // Two spaces before a word: the first stays alone, the second joins the word.
+        assert_eq!(vec![(32, 5), (263, 6)], tokenizer.encode_with_offsets("hello  world")[1..]);
+        assert_eq!(3, tokenizer.count_tokens("hello  world"));
+
+        let text = "héllo, wörld\n\n42";
+        assert_eq!(text, tokenizer.decode(&tokenizer.encode(text)));
+        assert!(Tokenizer::from_vocabulary("aGk= 0\n").is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::embed::{ChunkMetadata, CorpusMatch, EmbeddingPair, FileHash};
+use crate::bm25::tokenize;
+use crate::store::SearchFilter;
+
+use redis::aio::ConnectionManager;
+use redis::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Name of the RediSearch index over the stored chunks.
+pub const INDEX_NAME: &str = "dbsearch:idx";
+
+/// Key prefix of the Redis hashes holding one chunk each.
+pub const CHUNK_PREFIX: &str = "dbsearch:chunk:";
+
+/// Chunks fetched per FT.SEARCH request when listing the chunks of a file.
+const PAGE_SIZE: usize = 1000;
+
+/// Vector indexing algorithm used by RediSearch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum VectorAlgorithm {
+    #[default]
+    Hnsw,
+    Flat,
+}
+
+/// Distance metric used to compare embeddings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DistanceMetric {
+    #[default]
+    Cosine,
+    Ip,
+    L2,
+}
+
+/// The `vector_index` section of the configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VectorIndexConfig {
+    pub algorithm: VectorAlgorithm,
+    pub distance_metric: DistanceMetric,
+    /// HNSW: maximum number of outgoing edges per node.
+    pub m: usize,
+    /// HNSW: number of candidates considered while building the graph.
+    pub ef_construction: usize,
+}
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: VectorAlgorithm::Hnsw,
+            distance_metric: DistanceMetric::Cosine,
+            m: 16,
+            ef_construction: 200,
+        }
+    }
+}
+
+impl DistanceMetric {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DistanceMetric::Cosine => "COSINE",
+            DistanceMetric::Ip => "IP",
+            DistanceMetric::L2 => "L2",
+        }
+    }
+
+    /// Converts a RediSearch vector distance into a score where higher means more similar.
+    pub fn similarity(&self, distance: f32) -> f32 {
+        match self {
+            DistanceMetric::Cosine | DistanceMetric::Ip => 1.0 - distance,
+            DistanceMetric::L2 => -distance,
+        }
+    }
+}
+
+/// Returns the key of the Redis hash storing a single chunk of a file.
+pub fn chunk_key(file_sha256_hash: &str, position: usize) -> String {
+    format!("{}{}:{}", CHUNK_PREFIX, file_sha256_hash, position)
+}
+
+/// Encodes an embedding as the little-endian FLOAT32 blob RediSearch expects.
+pub fn embedding_to_bytes(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Decodes a little-endian FLOAT32 blob back into an embedding.
+pub fn bytes_to_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Escapes characters that have a meaning inside a RediSearch TAG query.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !c.is_alphanumeric() && c != '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Builds the clauses restricting a query to the chunks selected by `filter`.
+fn filter_clauses(filter: &SearchFilter) -> Vec<String> {
+    let mut clauses: Vec<String> = Vec::new();
+    if !filter.file_hashes.is_empty() {
+        let hashes: Vec<String> = filter.file_hashes.iter().map(|h| escape_tag(h)).collect();
+        clauses.push(format!("@file_hash:{{{}}}", hashes.join("|")));
+    }
+    if let Some(filename) = &filter.filename {
+        clauses.push(format!("@filename:{{{}}}", escape_tag(filename)));
+    }
+    if let Some((first, last)) = filter.positions {
+        clauses.push(format!("@position:[{} {}]", first, last));
+    }
+    clauses
+}
+
+/// Builds the FT.SEARCH query string for a KNN search restricted by `filter`.
+pub fn knn_query(filter: &SearchFilter, k: usize) -> String {
+    let clauses = filter_clauses(filter);
+    let prefilter = if clauses.is_empty() {
+        "*".to_string()
+    } else {
+        format!("({})", clauses.join(" "))
+    };
+    format!("{}=>[KNN {} @embedding $vec AS score]", prefilter, k)
+}
+
+/// Builds the FT.SEARCH query string matching chunks whose text contains any of `terms`,
+/// restricted by `filter`. Terms must come from [`tokenize`], which leaves nothing to escape.
+pub fn keyword_query(filter: &SearchFilter, terms: &[String]) -> String {
+    let mut clauses = filter_clauses(filter);
+    clauses.push(format!("@text:({})", terms.join("|")));
+    clauses.join(" ")
+}
+
+/// Returns the FT.INFO reply of the vector index, or `None` if it does not exist yet.
+async fn index_info(redis_connection: &mut ConnectionManager) -> RedisResult<Option<Vec<Value>>> {
+    match redis::cmd("FT.INFO").arg(INDEX_NAME).query_async(redis_connection).await {
+        Ok(info) => Ok(Some(info)),
+        // RediSearch answers "Unknown index name" or "no such index" for a missing index.
+        Err(e) if matches!(e.kind(), ErrorKind::ResponseError | ErrorKind::ExtensionError) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether the vector index exists.
+pub async fn index_exists(redis_connection: &mut ConnectionManager) -> RedisResult<bool> {
+    Ok(index_info(redis_connection).await?.is_some())
+}
+
+/// Number of chunks in the vector index, without scanning the keyspace.
+pub async fn index_document_count(redis_connection: &mut ConnectionManager) -> RedisResult<usize> {
+    let Some(info) = index_info(redis_connection).await? else {
+        return Ok(0);
+    };
+    for field in info.chunks_exact(2) {
+        if from_redis_value::<String>(&field[0])? == "num_docs" {
+            return Ok(from_redis_value::<String>(&field[1])?.parse().unwrap_or(0));
+        }
+    }
+    Ok(0)
+}
+
+/// Creates the vector index unless it already exists.
+pub async fn ensure_index(
+    redis_connection: &mut ConnectionManager,
+    config: &VectorIndexConfig,
+    dimensions: usize,
+) -> RedisResult<()> {
+    if index_exists(redis_connection).await? {
+        return Ok(());
+    }
+
+    let mut vector_args: Vec<String> = vec![
+        "TYPE".into(),
+        "FLOAT32".into(),
+        "DIM".into(),
+        dimensions.to_string(),
+        "DISTANCE_METRIC".into(),
+        config.distance_metric.as_str().into(),
+    ];
+    let algorithm = match config.algorithm {
+        VectorAlgorithm::Hnsw => {
+            vector_args.extend([
+                "M".into(),
+                config.m.to_string(),
+                "EF_CONSTRUCTION".into(),
+                config.ef_construction.to_string(),
+            ]);
+            "HNSW"
+        }
+        VectorAlgorithm::Flat => "FLAT",
+    };
+
+    redis::cmd("FT.CREATE")
+        .arg(INDEX_NAME)
+        .arg("ON")
+        .arg("HASH")
+        .arg("PREFIX")
+        .arg(1)
+        .arg(CHUNK_PREFIX)
+        .arg("SCHEMA")
+        .arg("file_hash")
+        .arg("TAG")
+        .arg("filename")
+        .arg("TAG")
+        .arg("SEPARATOR")
+        .arg("|")
+        .arg("position")
+        .arg("NUMERIC")
+        .arg("SORTABLE")
+        .arg("text")
+        .arg("TEXT")
+        .arg("embedding")
+        .arg("VECTOR")
+        .arg(algorithm)
+        .arg(vector_args.len())
+        .arg(vector_args)
+        .query_async(redis_connection)
+        .await
+}
+
+/// Stores one chunk as a Redis hash picked up by the vector index.
+pub async fn store_chunk(
+    redis_connection: &mut ConnectionManager,
+    file: &FileHash,
+    position: usize,
+    pair: &EmbeddingPair,
+) -> RedisResult<()> {
+    let metadata = &pair.metadata;
+    let mut fields: Vec<(&str, Vec<u8>)> = vec![
+        ("file_hash", file.hash.as_bytes().to_vec()),
+        ("filename", file.filename.as_bytes().to_vec()),
+        ("position", position.to_string().into_bytes()),
+        ("text", pair.text.as_bytes().to_vec()),
+        ("embedding", embedding_to_bytes(&pair.embedding)),
+        ("version", metadata.version.to_string().into_bytes()),
+        ("char_start", metadata.char_start.to_string().into_bytes()),
+        ("char_end", metadata.char_end.to_string().into_bytes()),
+        ("model", metadata.model.as_bytes().to_vec()),
+        ("created_at", metadata.created_at.to_string().into_bytes()),
+    ];
+    if let Some(page) = metadata.page {
+        fields.push(("page", page.to_string().into_bytes()));
+    }
+    redis_connection
+        .hset_multiple(chunk_key(&file.hash, position), &fields)
+        .await
+}
+
+fn field_string(fields: &HashMap<String, Vec<u8>>, name: &str) -> String {
+    fields
+        .get(name)
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+        .unwrap_or_default()
+}
+
+fn chunk_from_fields(fields: &HashMap<String, Vec<u8>>) -> CorpusMatch {
+    let number = |name: &str| field_string(fields, name).parse().unwrap_or(0);
+    let position = number("position") as usize;
+    let metadata = ChunkMetadata {
+        version: number("version") as u32,
+        document_id: field_string(fields, "file_hash"),
+        path: field_string(fields, "filename"),
+        page: field_string(fields, "page").parse().ok(),
+        char_start: number("char_start") as usize,
+        char_end: number("char_end") as usize,
+        ordinal: position,
+        model: field_string(fields, "model"),
+        created_at: number("created_at"),
+    };
+    CorpusMatch {
+        file: FileHash {
+            hash: metadata.document_id.clone(),
+            filename: metadata.path.clone(),
+            model: metadata.model.clone(),
+        },
+        position,
+        pair: EmbeddingPair::new(
+            field_string(fields, "text"),
+            fields
+                .get("embedding")
+                .map(|v| bytes_to_embedding(v))
+                .unwrap_or_default(),
+        )
+        .with_metadata(metadata),
+    }
+}
+
+/// Queries the index for every chunk of a file, page by page, returning the reply entries after
+/// the counts: keys only, or keys followed by their fields when `content` is set.
+async fn search_file(
+    redis_connection: &mut ConnectionManager,
+    file_sha256_hash: &str,
+    content: bool,
+) -> RedisResult<Vec<Value>> {
+    let query = format!("@file_hash:{{{}}}", escape_tag(file_sha256_hash));
+    let mut entries: Vec<Value> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut command = redis::cmd("FT.SEARCH");
+        command.arg(INDEX_NAME).arg(&query);
+        if content {
+            command.arg("SORTBY").arg("position");
+        } else {
+            command.arg("NOCONTENT");
+        }
+        let response: Vec<Value> = command
+            .arg("LIMIT")
+            .arg(offset)
+            .arg(PAGE_SIZE)
+            .arg("DIALECT")
+            .arg(2)
+            .query_async(redis_connection)
+            .await?;
+        let total: usize = match response.first() {
+            Some(count) => from_redis_value(count)?,
+            None => 0,
+        };
+        entries.extend(response.into_iter().skip(1));
+        offset += PAGE_SIZE;
+        if offset >= total {
+            return Ok(entries);
+        }
+    }
+}
+
+/// Returns the keys of every stored chunk of a file.
+pub async fn file_chunk_keys(
+    redis_connection: &mut ConnectionManager,
+    file_sha256_hash: &str,
+) -> RedisResult<Vec<String>> {
+    search_file(redis_connection, file_sha256_hash, false)
+        .await?
+        .iter()
+        .map(from_redis_value)
+        .collect()
+}
+
+/// Deletes every stored chunk of a file, returning how many were removed.
+pub async fn delete_file_chunks(
+    redis_connection: &mut ConnectionManager,
+    file_sha256_hash: &str,
+) -> RedisResult<usize> {
+    let keys = file_chunk_keys(redis_connection, file_sha256_hash).await?;
+    if keys.is_empty() {
+        return Ok(0);
+    }
+    redis_connection.del(keys).await
+}
+
+/// Loads every stored chunk of a file, ordered by position.
+pub async fn load_file_chunks(
+    redis_connection: &mut ConnectionManager,
+    file_sha256_hash: &str,
+) -> RedisResult<Vec<EmbeddingPair>> {
+    let entries = search_file(redis_connection, file_sha256_hash, true).await?;
+
+    let mut chunks: Vec<(usize, EmbeddingPair)> = Vec::new();
+    for document in entries.chunks_exact(2) {
+        let fields: HashMap<String, Vec<u8>> = from_redis_value(&document[1])?;
+        let chunk = chunk_from_fields(&fields);
+        chunks.push((chunk.position, chunk.pair));
+    }
+    chunks.sort_by_key(|(position, _)| *position);
+    Ok(chunks.into_iter().map(|(_, pair)| pair).collect())
+}
+
+/// Runs a server-side KNN query and returns the `k` nearest chunks, most similar first.
+pub async fn knn_search(
+    redis_connection: &mut ConnectionManager,
+    config: &VectorIndexConfig,
+    embedding: &[f32],
+    k: usize,
+    filter: &SearchFilter,
+) -> RedisResult<Vec<CorpusMatch>> {
+    let response: Vec<Value> = redis::cmd("FT.SEARCH")
+        .arg(INDEX_NAME)
+        .arg(knn_query(filter, k))
+        .arg("PARAMS")
+        .arg(2)
+        .arg("vec")
+        .arg(embedding_to_bytes(embedding))
+        .arg("SORTBY")
+        .arg("score")
+        .arg("LIMIT")
+        .arg(0)
+        .arg(k)
+        .arg("DIALECT")
+        .arg(2)
+        .query_async(redis_connection)
+        .await?;
+
+    // Reply layout: total count, then alternating document keys and field lists.
+    let mut matches: Vec<CorpusMatch> = Vec::new();
+    for document in response.iter().skip(1).skip(1).step_by(2) {
+        let fields: HashMap<String, Vec<u8>> = from_redis_value(document)?;
+        let distance: f32 = field_string(&fields, "score").parse().unwrap_or(f32::MAX);
+        let mut chunk = chunk_from_fields(&fields);
+        chunk.pair.similarity = config.distance_metric.similarity(distance);
+        matches.push(chunk);
+    }
+    Ok(matches)
+}
+
+/// Ranks the chunks containing words of `query` with the BM25 scorer of RediSearch's full-text
+/// index over the chunk text, and returns the `k` best ones.
+pub async fn keyword_search(
+    redis_connection: &mut ConnectionManager,
+    query: &str,
+    k: usize,
+    filter: &SearchFilter,
+) -> RedisResult<Vec<CorpusMatch>> {
+    let terms = tokenize(query);
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+    let response: Vec<Value> = redis::cmd("FT.SEARCH")
+        .arg(INDEX_NAME)
+        .arg(keyword_query(filter, &terms))
+        .arg("WITHSCORES")
+        .arg("SCORER")
+        .arg("BM25")
+        .arg("LIMIT")
+        .arg(0)
+        .arg(k)
+        .arg("DIALECT")
+        .arg(2)
+        .query_async(redis_connection)
+        .await?;
+
+    // Reply layout: total count, then document keys, scores and field lists in turn.
+    let mut matches: Vec<CorpusMatch> = Vec::new();
+    for document in response[1.min(response.len())..].chunks_exact(3) {
+        let score: f32 = from_redis_value::<String>(&document[1])?.parse().unwrap_or(0.0);
+        let fields: HashMap<String, Vec<u8>> = from_redis_value(&document[2])?;
+        let mut chunk = chunk_from_fields(&fields);
+        chunk.pair.similarity = score;
+        matches.push(chunk);
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_bytes_roundtrip() {
+        let embedding = vec![0.25, -1.5, 3.0];
+        assert_eq!(embedding, bytes_to_embedding(&embedding_to_bytes(&embedding)));
+    }
+
+    #[test]
+    fn test_knn_query() {
+        assert_eq!("*=>[KNN 3 @embedding $vec AS score]", knn_query(&SearchFilter::default(), 3));
+
+        let filter = SearchFilter {
+            file_hashes: vec!["ab12".to_string(), "cd34".to_string()],
+            filename: Some("docs/a b.pdf".to_string()),
+            positions: Some((2, 5)),
+        };
+        assert_eq!(
+            r"(@file_hash:{ab12|cd34} @filename:{docs\/a\ b\.pdf} @position:[2 5])=>[KNN 5 @embedding $vec AS score]",
+            knn_query(&filter, 5)
+        );
+    }
+
+    #[test]
+    fn test_keyword_query() {
+        let terms = vec!["xj".to_string(), "200".to_string()];
+        assert_eq!("@text:(xj|200)", keyword_query(&SearchFilter::default(), &terms));
+
+        let filter = SearchFilter {
+            file_hashes: vec!["ab12".to_string()],
+            ..Default::default()
+        };
+        assert_eq!("@file_hash:{ab12} @text:(xj|200)", keyword_query(&filter, &terms));
+    }
+}
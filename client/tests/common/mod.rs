@@ -0,0 +1,413 @@
+//! Mock backends shared by the integration tests: an in-memory vector store and a local
+//! HTTP server standing in for the OpenAI embeddings and chat completions endpoints.
+
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use chatgpt::prelude::*;
+use dbsearch::bm25::Bm25Index;
+use dbsearch::embed::{CorpusMatch, EmbeddingPair, FileHash};
+use dbsearch::embedder::{Embedder, ModelConfig};
+use dbsearch::math::cosine_similarity;
+use dbsearch::store::{DocumentRecord, SearchFilter, StoreResult, StoreStats, VectorStore};
+use dbsearch::Error;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Vector store keeping everything in memory.
+#[derive(Default)]
+pub struct MockStore {
+    chunks: Mutex<BTreeMap<(String, usize), (FileHash, EmbeddingPair)>>,
+    files: Mutex<BTreeMap<String, FileHash>>,
+    collections: Mutex<BTreeMap<String, BTreeSet<String>>>,
+    documents: Mutex<BTreeMap<String, DocumentRecord>>,
+    embedding_cache: Mutex<BTreeMap<String, Vec<f32>>>,
+    score_cache: Mutex<BTreeMap<String, f32>>,
+    /// When set, storing a chunk fails as if the store had become unreachable.
+    pub fail_writes: AtomicBool,
+}
+
+impl MockStore {
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl VectorStore for MockStore {
+    async fn store_chunk(&self, file: &FileHash, position: usize, pair: &EmbeddingPair) -> StoreResult<()> {
+        if self.fail_writes.load(Ordering::SeqCst) {
+            return Err(Error::Io(io::Error::other("store unreachable")));
+        }
+        self.chunks
+            .lock()
+            .unwrap()
+            .insert((file.hash.clone(), position), (file.clone(), pair.clone()));
+        Ok(())
+    }
+
+    async fn register_file(&self, file: &FileHash) -> StoreResult<()> {
+        self.files.lock().unwrap().insert(file.hash.clone(), file.clone());
+        Ok(())
+    }
+
+    async fn is_processed(&self, file_sha256_hash: &str) -> StoreResult<bool> {
+        Ok(self.files.lock().unwrap().contains_key(file_sha256_hash))
+    }
+
+    async fn get_file(&self, file_sha256_hash: &str) -> StoreResult<Option<FileHash>> {
+        Ok(self.files.lock().unwrap().get(file_sha256_hash).cloned())
+    }
+
+    async fn load_file_chunks(&self, file_sha256_hash: &str) -> StoreResult<Vec<EmbeddingPair>> {
+        Ok(self
+            .chunks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((hash, _), _)| hash == file_sha256_hash)
+            .map(|(_, (_, pair))| pair.clone())
+            .collect())
+    }
+
+    async fn list_files(&self, collection: Option<&str>) -> StoreResult<Vec<FileHash>> {
+        let files = self.files.lock().unwrap();
+        let mut listed: Vec<FileHash> = match collection {
+            Some(name) => self
+                .collections
+                .lock()
+                .unwrap()
+                .get(name)
+                .map(|hashes| hashes.iter().filter_map(|hash| files.get(hash).cloned()).collect())
+                .unwrap_or_default(),
+            None => files.values().cloned().collect(),
+        };
+        listed.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(listed)
+    }
+
+    async fn add_to_collection(&self, collection: &str, file_sha256_hash: &str) -> StoreResult<()> {
+        self.collections
+            .lock()
+            .unwrap()
+            .entry(collection.to_string())
+            .or_default()
+            .insert(file_sha256_hash.to_string());
+        Ok(())
+    }
+
+    async fn delete_file(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        self.files.lock().unwrap().remove(file_sha256_hash);
+        for members in self.collections.lock().unwrap().values_mut() {
+            members.remove(file_sha256_hash);
+        }
+        self.documents.lock().unwrap().retain(|_, record| record.hash != file_sha256_hash);
+        self.delete_chunks(file_sha256_hash).await
+    }
+
+    async fn delete_chunks(&self, file_sha256_hash: &str) -> StoreResult<usize> {
+        let mut chunks = self.chunks.lock().unwrap();
+        let before = chunks.len();
+        chunks.retain(|(hash, _), _| hash != file_sha256_hash);
+        Ok(before - chunks.len())
+    }
+
+    async fn get_document(&self, path: &str) -> StoreResult<Option<DocumentRecord>> {
+        Ok(self.documents.lock().unwrap().get(path).cloned())
+    }
+
+    async fn put_document(&self, record: &DocumentRecord) -> StoreResult<()> {
+        self.documents.lock().unwrap().insert(record.path.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn remove_document(&self, path: &str) -> StoreResult<()> {
+        self.documents.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    async fn list_documents(&self) -> StoreResult<Vec<DocumentRecord>> {
+        Ok(self.documents.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn get_cached_embeddings(&self, keys: &[String]) -> StoreResult<Vec<Option<Vec<f32>>>> {
+        let cache = self.embedding_cache.lock().unwrap();
+        Ok(keys.iter().map(|key| cache.get(key).cloned()).collect())
+    }
+
+    async fn cache_embeddings(&self, entries: &[(String, Vec<f32>)]) -> StoreResult<()> {
+        self.embedding_cache.lock().unwrap().extend(entries.iter().cloned());
+        Ok(())
+    }
+
+    async fn get_cached_scores(&self, keys: &[String]) -> StoreResult<Vec<Option<f32>>> {
+        let cache = self.score_cache.lock().unwrap();
+        Ok(keys.iter().map(|key| cache.get(key).copied()).collect())
+    }
+
+    async fn cache_scores(&self, entries: &[(String, f32)]) -> StoreResult<()> {
+        self.score_cache.lock().unwrap().extend(entries.iter().cloned());
+        Ok(())
+    }
+
+    async fn stats(&self) -> StoreResult<StoreStats> {
+        Ok(StoreStats {
+            files: self.files.lock().unwrap().len(),
+            chunks: self.chunks.lock().unwrap().len(),
+            collections: self.collections.lock().unwrap().len(),
+        })
+    }
+
+    async fn search(&self, embedding: &[f32], k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        let mut matches: Vec<CorpusMatch> = self
+            .chunks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((_, position), (file, _))| filter.matches(file, *position))
+            .map(|((_, position), (file, pair))| {
+                let mut pair = pair.clone();
+                pair.similarity = cosine_similarity(embedding, &pair.embedding);
+                CorpusMatch {
+                    file: file.clone(),
+                    position: *position,
+                    pair,
+                }
+            })
+            .collect();
+        matches.sort_by(|a, b| b.pair.similarity.total_cmp(&a.pair.similarity));
+        matches.truncate(k);
+        Ok(matches)
+    }
+
+    async fn keyword_search(&self, query: &str, k: usize, filter: &SearchFilter) -> StoreResult<Vec<CorpusMatch>> {
+        let chunks = self.chunks.lock().unwrap();
+        let mut keywords = Bm25Index::default();
+        for (key, (_, pair)) in chunks.iter() {
+            keywords.insert(key.clone(), &pair.text);
+        }
+        let ranked = keywords.search(query, k, |key| filter.matches(&chunks[key].0, key.1));
+        Ok(ranked
+            .into_iter()
+            .map(|(key, score)| {
+                let (file, pair) = &chunks[&key];
+                let mut pair = pair.clone();
+                pair.similarity = score;
+                CorpusMatch {
+                    file: file.clone(),
+                    position: key.1,
+                    pair,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Embeds text as its normalized letter histogram, so texts sharing words end up close.
+pub fn letter_embedding(text: &str) -> Vec<f32> {
+    let mut embedding = vec![0.0f32; 27];
+    for c in text.to_lowercase().chars() {
+        match c {
+            'a'..='z' => embedding[(c as u8 - b'a') as usize] += 1.0,
+            _ => embedding[26] += 0.01,
+        }
+    }
+    embedding
+}
+
+/// Lowercase words of `text`.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Answers a rerank prompt, scoring every passage by the number of question words it contains.
+/// Prompts with a "garbled" passage get a reply without scores.
+fn relevance_scores(prompt: &str) -> String {
+    if prompt.contains("garbled") {
+        return "I cannot rate these passages.".to_string();
+    }
+    let mut parts = prompt.split("\n\nPassage ");
+    let question = words(parts.next().unwrap_or_default().trim_start_matches("Question:"));
+    let scores: BTreeMap<String, usize> = parts
+        .filter_map(|part| part.split_once(":\n"))
+        .map(|(number, passage)| (number.to_string(), words(passage).intersection(&question).count()))
+        .collect();
+    format!("Scores: {}", json!(scores))
+}
+
+/// Local stand-in for the OpenAI API.
+pub struct MockOpenAi {
+    pub url: String,
+    /// Number of embeddings requests received.
+    pub embedding_requests: Arc<AtomicUsize>,
+    /// Number of chat completion requests received.
+    pub chat_requests: Arc<AtomicUsize>,
+}
+
+impl MockOpenAi {
+    /// Starts the server on a free local port.
+    ///
+    /// Embeddings are [`letter_embedding`]s; chat completions answer with the first source
+    /// label found in the messages, so answers cite a source, and rerank prompts with
+    /// [`relevance_scores`]. Streamed completions arrive as server-sent events, which are
+    /// malformed when the last message asks to "break the stream".
+    pub async fn start() -> MockOpenAi {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let embedding_requests = Arc::new(AtomicUsize::new(0));
+        let chat_requests = Arc::new(AtomicUsize::new(0));
+
+        let counters = (embedding_requests.clone(), chat_requests.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counters = counters.clone();
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, counters).await;
+                });
+            }
+        });
+        MockOpenAi {
+            url,
+            embedding_requests,
+            chat_requests,
+        }
+    }
+
+    /// Model configuration pointing both endpoints at this server.
+    pub fn model_config(&self) -> ModelConfig {
+        ModelConfig {
+            api_url: Some(format!("{}/v1/chat/completions", self.url)),
+            embed_api_url: Some(format!("{}/v1/embeddings", self.url)),
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Embedder talking to this server.
+    pub fn embedder(&self) -> Embedder {
+        let config = self.model_config().to_model_configuration().unwrap();
+        Embedder::new(ChatGPT::new_with_config("test-key", config).unwrap())
+    }
+}
+
+async fn serve_connection(stream: TcpStream, counters: (Arc<AtomicUsize>, Arc<AtomicUsize>)) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    loop {
+        // Request line and headers; only Content-Length matters.
+        let mut content_length = 0;
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).await?;
+        let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+        let (body, content_type) = if let Some(inputs) = request["input"].as_array() {
+            counters.0.fetch_add(1, Ordering::SeqCst);
+            let data: Vec<Value> = inputs
+                .iter()
+                .enumerate()
+                .map(|(index, input)| {
+                    json!({
+                        "object": "embedding",
+                        "index": index,
+                        "embedding": letter_embedding(input.as_str().unwrap_or_default()),
+                    })
+                })
+                .collect();
+            let response = json!({
+                "object": "list",
+                "model": request["model"],
+                "usage": { "prompt_tokens": 1, "total_tokens": 1 },
+                "data": data,
+            });
+            (response.to_string(), "application/json")
+        } else {
+            counters.1.fetch_add(1, Ordering::SeqCst);
+            let messages = request["messages"].as_array().cloned().unwrap_or_default();
+            let last = messages.last().and_then(|message| message["content"].as_str()).unwrap_or_default();
+            let label = messages
+                .iter()
+                .flat_map(|message| message["content"].as_str().unwrap_or_default().lines())
+                .find(|line| line.starts_with('[') && line.ends_with(']') && line.contains('#'))
+                .unwrap_or("[none]");
+            let answer = if last.contains("\n\nPassage 1:\n") {
+                relevance_scores(last)
+            } else {
+                format!("See {}.", label)
+            };
+            if request["stream"].as_bool().unwrap_or(false) && last.contains("break the stream") {
+                ("data: {\"choices\": [{\"index\": 0,\n\n".to_string(), "text/event-stream")
+            } else if request["stream"].as_bool().unwrap_or(false) {
+                (event_stream(&answer), "text/event-stream")
+            } else {
+                let response = json!({
+                    "id": "chatcmpl-test",
+                    "created": 0,
+                    "model": request["model"],
+                    "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+                    "choices": [{
+                        "index": 0,
+                        "finish_reason": "stop",
+                        "message": { "role": "assistant", "content": answer },
+                    }],
+                });
+                (response.to_string(), "application/json")
+            }
+        };
+
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        );
+        let stream = reader.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.flush().await?;
+    }
+}
+
+/// Server-sent events streaming `answer` one word at a time, as the API does for `"stream": true`.
+fn event_stream(answer: &str) -> String {
+    let mut deltas = vec![json!({ "role": "assistant" })];
+    deltas.extend(answer.split_inclusive(' ').map(|word| json!({ "content": word })));
+    deltas.push(json!({}));
+    let mut events: String = deltas
+        .into_iter()
+        .map(|delta| format!("data: {}\n\n", json!({ "choices": [{ "index": 0, "delta": delta }] })))
+        .collect();
+    events.push_str("data: [DONE]\n\n");
+    events
+}
+
+/// Creates an empty scratch directory unique to `name`.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dbsearch-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,486 @@
+mod common;
+
+use chatgpt::types::Role;
+use common::{scratch_dir, MockOpenAi, MockStore};
+use dbsearch::answer::ask;
+use dbsearch::chat::ChatSession;
+use dbsearch::chunker::{ChunkingConfig, TextChunker};
+use dbsearch::embed::{search_corpus, CorpusMatch, FileHash, IndexEvent};
+use dbsearch::index::index_directory;
+use dbsearch::prompt::PromptConfig;
+use dbsearch::rerank::RerankConfig;
+use dbsearch::retrieval::{retrieve, RetrievalConfig, RetrievalMode};
+use dbsearch::search::WalkOptions;
+use dbsearch::store::{document_key, DocumentRecord, DocumentStatus, SearchFilter, VectorStore};
+use dbsearch::Error;
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+fn ignore_events(_: &str, _: IndexEvent) {}
+
+/// Writes a small corpus and indexes it into `store` through the mock API.
+async fn index_corpus(name: &str, store: &MockStore, api: &MockOpenAi) -> std::path::PathBuf {
+    let root = scratch_dir(name);
+    fs::write(root.join("apples.txt"), "Apples are crisp red fruit picked in autumn.").unwrap();
+    fs::write(root.join("zebras.md"), "# Zebras\n\nZebras have black and white stripes.").unwrap();
+    fs::write(root.join("image.bin"), [0u8, 159, 146, 150]).unwrap();
+
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let summary = index_directory(
+        store,
+        &api.embedder(),
+        &chunker,
+        &root,
+        &WalkOptions::default(),
+        Some("corpus"),
+        2,
+        &ignore_events,
+    )
+    .await
+    .unwrap();
+    assert_eq!((2, 0, 1, 0), (summary.embedded, summary.skipped, summary.unsupported, summary.failed));
+    root
+}
+
+#[tokio::test]
+async fn test_index_and_search() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("search", &store, &api).await;
+    assert_eq!(2, store.chunk_count());
+    assert_eq!(2, store.list_files(Some("corpus")).await.unwrap().len());
+
+    // Indexing again finds every file already processed and embeds nothing.
+    let requests = api.embedding_requests.load(Ordering::SeqCst);
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let summary = index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!(2, summary.skipped);
+    assert_eq!(requests, api.embedding_requests.load(Ordering::SeqCst));
+
+    let matches = search_corpus(&store, &api.embedder(), "crisp red apples", 1, Some("corpus"), &RetrievalConfig::default()).await.unwrap();
+    assert_eq!(1, matches.len());
+    assert!(matches[0].file.filename.ends_with("apples.txt"));
+    assert_eq!(api.embedder().model(), matches[0].pair.metadata.model);
+
+    assert!(search_corpus(&store, &api.embedder(), "apples", 1, Some("other"), &RetrievalConfig::default()).await.unwrap().is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_reindex_changed_and_deleted_files() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("reindex", &store, &api).await;
+    let apples = root.join("apples.txt");
+    let old_record = store.get_document(&document_key(&apples)).await.unwrap().unwrap();
+    assert_eq!(DocumentStatus::Complete, old_record.status);
+    assert_eq!((1, vec!["corpus".to_string()]), (old_record.chunks, old_record.collections.clone()));
+
+    // An edited file replaces its old chunks and stays in its collection.
+    fs::write(&apples, "Apples are sweet green fruit.").unwrap();
+    fs::remove_file(root.join("zebras.md")).unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let events = Mutex::new(Vec::new());
+    let record_event = |path: &str, event: IndexEvent| events.lock().unwrap().push((path.to_string(), event));
+    let summary = index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &record_event)
+        .await
+        .unwrap();
+    assert_eq!((0, 1, 1), (summary.embedded, summary.changed, summary.removed));
+    let events = events.into_inner().unwrap();
+    assert!(events.contains(&(root.to_string_lossy().into_owned(), IndexEvent::Found { files: 2 })));
+    assert!(events.contains(&(apples.to_string_lossy().into_owned(), IndexEvent::Embedding)));
+    assert!(events.contains(&(document_key(&root.join("zebras.md")), IndexEvent::Removed)));
+
+    let record = store.get_document(&document_key(&apples)).await.unwrap().unwrap();
+    assert_ne!(old_record.hash, record.hash);
+    assert_eq!((DocumentStatus::Complete, None), (record.status, record.previous_hash));
+    assert!(store.get_file(&old_record.hash).await.unwrap().is_none());
+    assert_eq!(1, store.chunk_count());
+    assert_eq!(1, store.list_documents().await.unwrap().len());
+    let matches = search_corpus(&store, &api.embedder(), "green apples", 5, Some("corpus"), &RetrievalConfig::default()).await.unwrap();
+    assert_eq!("Apples are sweet green fruit.", matches[0].pair.text);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_copies_share_indexed_content() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("shared", &store, &api).await;
+    let apples = store.get_document(&document_key(&root.join("apples.txt"))).await.unwrap().unwrap();
+    let copies = scratch_dir("shared-copies");
+    fs::copy(root.join("apples.txt"), copies.join("apples.txt")).unwrap();
+
+    // Re-embedding a copy indexed with another model replaces the chunks only.
+    let stale = FileHash {
+        model: "old-model".to_string(),
+        ..store.get_file(&apples.hash).await.unwrap().unwrap()
+    };
+    store.register_file(&stale).await.unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let summary = index_directory(&store, &api.embedder(), &chunker, &copies, &WalkOptions::default(), Some("copies"), 1, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!(1, summary.embedded);
+    assert_eq!(Some(apples.clone()), store.get_document(&apples.path).await.unwrap());
+    assert_eq!(2, store.list_files(Some("corpus")).await.unwrap().len());
+    assert_eq!(api.embedder().model(), store.get_file(&apples.hash).await.unwrap().unwrap().model);
+    assert_eq!(2, store.chunk_count());
+
+    // Removing the copy leaves the original indexed.
+    fs::remove_dir_all(&copies).unwrap();
+    fs::create_dir_all(&copies).unwrap();
+    let summary = index_directory(&store, &api.embedder(), &chunker, &copies, &WalkOptions::default(), None, 1, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!(1, summary.removed);
+    assert_eq!(Some(apples.clone()), store.get_document(&apples.path).await.unwrap());
+    assert_eq!(1, store.load_file_chunks(&apples.hash).await.unwrap().len());
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&copies).unwrap();
+}
+
+#[tokio::test]
+async fn test_embedding_cache() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("cache", &store, &api).await;
+
+    // A reflowed copy of an indexed chunk, and two new files sharing a chunk.
+    let copies = scratch_dir("cache-copies");
+    fs::write(copies.join("apples.txt"), "Apples are crisp red fruit\npicked in autumn.").unwrap();
+    fs::write(copies.join("pears.csv"), "name,taste\npear,sweet\n").unwrap();
+    fs::write(copies.join("more-pears.csv"), "name,taste\npear,sweet\n\n").unwrap();
+    let requests = api.embedding_requests.load(Ordering::SeqCst);
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let summary = index_directory(&store, &api.embedder(), &chunker, &copies, &WalkOptions::default(), None, 1, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!(3, summary.embedded);
+    assert_eq!((2, 1), (summary.cache_hits, summary.cache_misses));
+    assert_eq!(requests + 1, api.embedding_requests.load(Ordering::SeqCst));
+
+    // Cached embeddings outlive the files they were computed for.
+    for file in store.list_files(None).await.unwrap() {
+        store.delete_file(&file.hash).await.unwrap();
+    }
+    let summary = index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 1, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!((2, 0), (summary.cache_hits, summary.cache_misses));
+    assert_eq!(requests + 1, api.embedding_requests.load(Ordering::SeqCst));
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&copies).unwrap();
+}
+
+#[tokio::test]
+async fn test_storage_failure_stops_indexing() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    store.fail_writes.store(true, Ordering::SeqCst);
+    let root = scratch_dir("store-failure");
+    fs::write(root.join("apples.txt"), "Apples are crisp red fruit.").unwrap();
+    fs::write(root.join("zebras.txt"), "Zebras have stripes.").unwrap();
+
+    // Local I/O errors from the store stop the walk just like Redis ones, after the first file.
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let requests = api.embedding_requests.load(Ordering::SeqCst);
+    let result = index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 1, &ignore_events).await;
+    assert!(matches!(&result, Err(Error::Store(inner)) if matches!(**inner, Error::Io(_))));
+    assert_eq!(requests + 1, api.embedding_requests.load(Ordering::SeqCst));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_interrupted_indexing() {
+    let api = MockOpenAi::start().await;
+    let root = scratch_dir("resume");
+    let notes = root.join("notes.md");
+    let sections: Vec<String> = (0..4).map(|i| format!("# Part {}\n\n{}", i, "Words about fruit. ".repeat(40))).collect();
+    fs::write(&notes, sections.join("\n\n")).unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+
+    // Embed the file once to get its chunks, then store half of them as an interrupted run would.
+    let complete = MockStore::default();
+    index_directory(&complete, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 1, &ignore_events)
+        .await
+        .unwrap();
+    let record = complete.get_document(&document_key(&notes)).await.unwrap().unwrap();
+    let chunks = complete.load_file_chunks(&record.hash).await.unwrap();
+    assert!(chunks.len() > 1);
+
+    let store = MockStore::default();
+    for pair in &chunks[..chunks.len() / 2] {
+        let file = FileHash {
+            hash: pair.metadata.document_id.clone(),
+            filename: pair.metadata.path.clone(),
+            model: pair.metadata.model.clone(),
+        };
+        store.store_chunk(&file, pair.metadata.ordinal, pair).await.unwrap();
+    }
+    let pending = DocumentRecord {
+        status: DocumentStatus::Pending,
+        chunks: 0,
+        ..record.clone()
+    };
+    store.put_document(&pending).await.unwrap();
+
+    let requests = api.embedding_requests.load(Ordering::SeqCst);
+    let summary = index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 1, &ignore_events)
+        .await
+        .unwrap();
+    assert_eq!(1, summary.resumed);
+    assert_eq!(chunks.len(), store.chunk_count());
+    assert_eq!(record, store.get_document(&record.path).await.unwrap().unwrap());
+    // Only the missing half was embedded, in a single batch.
+    assert_eq!(requests + 1, api.embedding_requests.load(Ordering::SeqCst));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_hybrid_retrieval() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = scratch_dir("hybrid");
+    fs::write(root.join("parts.txt"), "Order part XJ-200 for the pump.").unwrap();
+    fs::write(root.join("jazz.txt"), "Jinxed xylophones jazz up juxtaposed jukeboxes.").unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &ignore_events)
+        .await
+        .unwrap();
+
+    let filenames = |mode: RetrievalMode| {
+        let config = RetrievalConfig {
+            mode,
+            ..Default::default()
+        };
+        let (store, embedder) = (&store, api.embedder());
+        async move {
+            retrieve(store, &embedder, "XJ-200", 2, &SearchFilter::default(), &config)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|m| m.file.filename.rsplit('/').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    // The letters of the identifier are spread all over the other file, which fools the
+    // embeddings but not the keyword index.
+    assert_eq!(vec!["jazz.txt", "parts.txt"], filenames(RetrievalMode::Vector).await);
+    assert_eq!(vec!["parts.txt"], filenames(RetrievalMode::Keyword).await);
+    assert_eq!(vec!["parts.txt", "jazz.txt"], filenames(RetrievalMode::Hybrid).await);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_mmr_diversifies_sources() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("mmr", &store, &api).await;
+    fs::write(root.join("apples-copy.txt"), "Apples are crisp red fruits picked in autumn.").unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &ignore_events)
+        .await
+        .unwrap();
+
+    let sources = |mmr_lambda: Option<f32>| {
+        let config = RetrievalConfig {
+            mmr_lambda,
+            ..Default::default()
+        };
+        let (store, embedder) = (&store, api.embedder());
+        async move {
+            retrieve(store, &embedder, "crisp red apples", 2, &SearchFilter::default(), &config)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|m| m.file.filename.rsplit('/').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    let mut similar = sources(None).await;
+    similar.sort();
+    assert_eq!(vec!["apples-copy.txt", "apples.txt"], similar);
+    let diverse = sources(Some(0.3)).await;
+    assert_eq!(2, diverse.len());
+    assert!(diverse.contains(&"zebras.md".to_string()));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_llm_rerank() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = scratch_dir("rerank");
+    fs::write(root.join("parts.txt"), "Order part XJ-200 for the pump.").unwrap();
+    fs::write(root.join("jazz.txt"), "Jinxed xylophones jazz up juxtaposed jukeboxes.").unwrap();
+    fs::write(root.join("pumps.txt"), "Pump XJ-200 uses part XJ-100 instead.").unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &ignore_events)
+        .await
+        .unwrap();
+
+    let config = RetrievalConfig {
+        rerank: Some(RerankConfig {
+            candidates: 3,
+            batch_size: 2,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let query = "Which pump uses part XJ-200?";
+    let matches = retrieve(&store, &api.embedder(), query, 2, &SearchFilter::default(), &config).await.unwrap();
+    let filenames: Vec<&str> = matches.iter().map(|m| m.file.filename.rsplit('/').next().unwrap()).collect();
+    assert_eq!(vec!["pumps.txt", "parts.txt"], filenames);
+    assert_eq!(0.5, matches[0].pair.similarity);
+    assert_eq!(2, api.chat_requests.load(Ordering::SeqCst));
+
+    // Scores are cached per query and chunk, so asking again costs no chat request.
+    let again = retrieve(&store, &api.embedder(), query, 2, &SearchFilter::default(), &config).await.unwrap();
+    assert_eq!(matches[0].pair.text, again[0].pair.text);
+    assert_eq!(2, api.chat_requests.load(Ordering::SeqCst));
+    retrieve(&store, &api.embedder(), "Which part?", 2, &SearchFilter::default(), &config).await.unwrap();
+    assert_eq!(4, api.chat_requests.load(Ordering::SeqCst));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_llm_rerank_keeps_unscored_batch_in_place() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = scratch_dir("rerank-garbled");
+    fs::write(root.join("parts.txt"), "Order part XJ-200 for the pump.").unwrap();
+    fs::write(root.join("garbled.txt"), "Which pump uses part XJ-200? The garbled answer.").unwrap();
+    fs::write(root.join("pumps.txt"), "Pump XJ-200 uses part XJ-100 instead.").unwrap();
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    index_directory(&store, &api.embedder(), &chunker, &root, &WalkOptions::default(), None, 2, &ignore_events)
+        .await
+        .unwrap();
+
+    let query = "Which pump uses part XJ-200?";
+    let filenames = |matches: &[CorpusMatch]| -> Vec<String> {
+        matches.iter().map(|m| m.file.filename.rsplit('/').next().unwrap().to_string()).collect()
+    };
+    let candidates = retrieve(&store, &api.embedder(), query, 3, &SearchFilter::default(), &RetrievalConfig::default())
+        .await
+        .unwrap();
+    assert_eq!("garbled.txt", filenames(&candidates)[0]);
+
+    // The garbled chunk's reply holds no scores, so it keeps leading instead of ranking last, and
+    // nothing is cached for it.
+    let config = RetrievalConfig {
+        rerank: Some(RerankConfig {
+            candidates: 3,
+            batch_size: 1,
+            concurrency: 1,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let matches = retrieve(&store, &api.embedder(), query, 3, &SearchFilter::default(), &config).await.unwrap();
+    assert_eq!(3, api.chat_requests.load(Ordering::SeqCst));
+    let ranked = filenames(&matches);
+    assert_eq!("garbled.txt", ranked[0]);
+    let others: Vec<&String> = ranked.iter().filter(|name| *name != "garbled.txt").collect();
+    assert_eq!(vec!["pumps.txt", "parts.txt"], others);
+    assert!(matches.windows(2).all(|pair| pair[0].pair.similarity >= pair[1].pair.similarity));
+
+    retrieve(&store, &api.embedder(), query, 3, &SearchFilter::default(), &config).await.unwrap();
+    assert_eq!(4, api.chat_requests.load(Ordering::SeqCst));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_ask() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("ask", &store, &api).await;
+
+    let config = PromptConfig {
+        top_k: 1,
+        ..Default::default()
+    };
+    let answer = ask(
+        &store,
+        &api.embedder(),
+        "You answer questions about animals.",
+        "What stripes do zebras have?",
+        &config,
+        &RetrievalConfig::default(),
+        None,
+        &SearchFilter::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(1, api.chat_requests.load(Ordering::SeqCst));
+    assert_eq!(1, answer.prompt.sources.len());
+    assert!(answer.prompt.sources[0].file.filename.ends_with("zebras.md"));
+    assert_eq!(1, answer.prompt.cited_sources(&answer.text).len());
+    assert!(answer.with_sources().contains("zebras.md#0"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_chat() {
+    let api = MockOpenAi::start().await;
+    let store = MockStore::default();
+    let root = index_corpus("chat", &store, &api).await;
+    let embedder = api.embedder();
+    let config = PromptConfig {
+        top_k: 1,
+        ..Default::default()
+    };
+
+    let mut session = ChatSession::new(&embedder, "You answer questions about the corpus.", SearchFilter::default());
+    let mut streamed = String::new();
+    let answer = session
+        .ask(&store, &embedder, "What stripes do zebras have?", &config, None, &mut |delta: &str| streamed.push_str(delta))
+        .await
+        .unwrap();
+    assert_eq!(streamed, answer.text);
+    assert!(answer.text.contains("zebras.md#0"));
+
+    // Every turn retrieves its own sources.
+    let answer = session
+        .ask(&store, &embedder, "And which fruit is crisp and red?", &config, None, &mut |_: &str| {})
+        .await
+        .unwrap();
+    assert!(answer.text.contains("apples.txt#0"));
+    assert_eq!(2, api.chat_requests.load(Ordering::SeqCst));
+
+    // The history keeps the bare questions, not the sources sent with them.
+    let history = session.history();
+    assert_eq!(5, history.len());
+    assert_eq!(Role::User, history[3].role);
+    assert_eq!("And which fruit is crisp and red?", history[3].content);
+    assert_eq!(answer.text, history[4].content);
+
+    let saved = root.join("chat.json");
+    session.save(&saved).await.unwrap();
+    let restored = ChatSession::restore(&embedder, &saved, SearchFilter::default()).await.unwrap();
+    assert_eq!(history.len(), restored.history().len());
+    assert_eq!(history[1].content, restored.history()[1].content);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_index_missing_directory() {
+    let api = MockOpenAi::start().await;
+    let chunker = TextChunker::from_config(&ChunkingConfig::default()).unwrap();
+    let result = index_directory(
+        &MockStore::default(),
+        &api.embedder(),
+        &chunker,
+        &scratch_dir("missing").join("nowhere"),
+        &WalkOptions::default(),
+        None,
+        1,
+        &ignore_events,
+    )
+    .await;
+    assert!(matches!(result, Err(Error::NotFound(_))));
+}
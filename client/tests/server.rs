@@ -0,0 +1,166 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use common::{scratch_dir, MockOpenAi, MockStore};
+use dbsearch::chunker::{ChunkingConfig, TextChunker};
+use dbsearch::prompt::PromptConfig;
+use dbsearch::server::{router, ServerState};
+use dbsearch::store::{DocumentRecord, DocumentStatus, VectorStore};
+use serde_json::{json, Value};
+use std::fs;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+fn app(api: &MockOpenAi, store: Arc<MockStore>) -> Router {
+    router(Arc::new(ServerState {
+        store,
+        embedder: api.embedder(),
+        chunker: TextChunker::from_config(&ChunkingConfig::default()).unwrap(),
+        agent_prompt: "You answer questions about the corpus.".to_string(),
+        prompt: PromptConfig {
+            top_k: 1,
+            ..Default::default()
+        },
+        retrieval: Default::default(),
+        concurrency: 2,
+        root: std::env::temp_dir(),
+    }))
+}
+
+/// Sends a request with an optional JSON body, returning the status, content type and body.
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, String, String) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, content_type, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Writes a small corpus and indexes it through the API.
+async fn index_corpus(name: &str, app: &Router) -> std::path::PathBuf {
+    let root = scratch_dir(name);
+    fs::write(root.join("apples.txt"), "Apples are crisp red fruit picked in autumn.").unwrap();
+    fs::write(root.join("zebras.md"), "# Zebras\n\nZebras have black and white stripes.").unwrap();
+
+    let request = json!({ "paths": [root], "collection": "corpus" });
+    let (status, _, body) = send(app, Method::POST, "/documents", Some(request)).await;
+    assert_eq!(StatusCode::OK, status);
+    let summary: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(2, summary["embedded"]);
+    root
+}
+
+#[tokio::test]
+async fn test_documents_and_search() {
+    let api = MockOpenAi::start().await;
+    let app = app(&api, Arc::default());
+    let root = index_corpus("server-documents", &app).await;
+
+    let (status, _, body) = send(&app, Method::GET, "/documents?collection=corpus", None).await;
+    assert_eq!(StatusCode::OK, status);
+    let documents: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(2, documents.len());
+
+    let request = json!({ "query": "crisp red apples", "collection": "corpus" });
+    let (status, _, body) = send(&app, Method::POST, "/search", Some(request)).await;
+    assert_eq!(StatusCode::OK, status);
+    let hits: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(1, hits.len());
+    assert!(hits[0]["filename"].as_str().unwrap().ends_with("apples.txt"));
+    assert!(hits[0].get("embedding").is_none());
+
+    let request = json!({ "query": "zebras stripes", "collection": "corpus", "mode": "keyword", "top_k": 5 });
+    let (status, _, body) = send(&app, Method::POST, "/search", Some(request)).await;
+    assert_eq!(StatusCode::OK, status);
+    let hits: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(1, hits.len());
+    assert!(hits[0]["filename"].as_str().unwrap().ends_with("zebras.md"));
+
+    let hash = hits[0]["hash"].as_str().unwrap().to_string();
+    let (status, _, body) = send(&app, Method::DELETE, &format!("/documents/{}", hash), None).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(json!({ "hash": hash, "chunks": 1 }), serde_json::from_str::<Value>(&body).unwrap());
+
+    let (status, _, body) = send(&app, Method::DELETE, &format!("/documents/{}", hash), None).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert!(serde_json::from_str::<Value>(&body).unwrap()["error"].is_string());
+
+    let (_, _, body) = send(&app, Method::GET, "/documents", None).await;
+    assert_eq!(1, serde_json::from_str::<Vec<Value>>(&body).unwrap().len());
+
+    let request = json!({ "paths": [root.join("missing")] });
+    let (status, _, _) = send(&app, Method::POST, "/documents", Some(request)).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+
+    // Paths outside the server's root are refused, however they are spelled.
+    let request = json!({ "paths": [root.join("../..")] });
+    let (status, _, _) = send(&app, Method::POST, "/documents", Some(request)).await;
+    assert_eq!(StatusCode::BAD_REQUEST, status);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn test_delete_unfinished_document() {
+    let api = MockOpenAi::start().await;
+    let store = Arc::new(MockStore::default());
+    let record = DocumentRecord {
+        path: "/data/notes.md".to_string(),
+        hash: "f00d".to_string(),
+        status: DocumentStatus::Failed,
+        ..Default::default()
+    };
+    store.put_document(&record).await.unwrap();
+    let app = app(&api, store.clone());
+
+    let (status, _, body) = send(&app, Method::DELETE, "/documents/f00d", None).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(json!({ "hash": "f00d", "chunks": 0 }), serde_json::from_str::<Value>(&body).unwrap());
+    assert!(store.list_documents().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_ask_streams_events() {
+    let api = MockOpenAi::start().await;
+    let app = app(&api, Arc::default());
+    let root = index_corpus("server-ask", &app).await;
+
+    let request = json!({ "query": "What stripes do zebras have?", "collection": "corpus" });
+    let (status, content_type, body) = send(&app, Method::POST, "/ask", Some(request)).await;
+    assert_eq!(StatusCode::OK, status);
+    assert!(content_type.starts_with("text/event-stream"));
+
+    let events: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("event:"))
+        .map(str::trim)
+        .collect();
+    assert_eq!(Some(&"sources"), events.first());
+    assert_eq!(Some(&"done"), events.last());
+    assert!(events.contains(&"delta"));
+    assert!(body.contains("zebras.md#0"));
+
+    // A broken answer stream ends with an error event.
+    let request = json!({ "query": "Zebras, but break the stream", "collection": "corpus" });
+    let (status, _, body) = send(&app, Method::POST, "/ask", Some(request)).await;
+    assert_eq!(StatusCode::OK, status);
+    let last_event = body.lines().filter_map(|line| line.strip_prefix("event:")).next_back();
+    assert_eq!(Some("error"), last_event.map(str::trim));
+
+    let request = json!({ "query": "Anything?", "collection": "missing" });
+    let (status, _, _) = send(&app, Method::POST, "/ask", Some(request)).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    fs::remove_dir_all(&root).unwrap();
+}